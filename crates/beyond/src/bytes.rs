@@ -0,0 +1,183 @@
+use std::ops::{Deref, DerefMut};
+
+use base64::prelude::*;
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{self, SeqAccess, Visitor},
+};
+
+/// Binary data in a request or response.
+///
+/// A `Vec<u8>` is encoded as a list of numbers, which takes up to four times its
+/// size in JSON. `Bytes` are encoded as a byte string in binary codecs, so that they
+/// are passed through as they are with binary framing, and as a single base64 string
+/// in JSON.
+///
+/// With JSON and text framing, the whole payload is base64-encoded once more, so the
+/// bytes take about 1.8 times their size. Large data should use binary framing or a
+/// binary codec instead.
+///
+/// `Bytes` also decode payloads with a `Vec<u8>` in their place, but a `Vec<u8>` does not
+/// decode `Bytes`. A field can only be changed from `Vec<u8>` to `Bytes` once every side
+/// that reads it is upgraded, see [`evolution`](crate::evolution).
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bytes(Vec<u8>);
+
+impl Bytes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl std::fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Bytes({} bytes)", self.0.len())
+    }
+}
+
+impl Deref for Bytes {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+impl DerefMut for Bytes {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.0
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(bytes: &[u8]) -> Self {
+        Self(bytes.to_vec())
+    }
+}
+
+impl From<Bytes> for Vec<u8> {
+    fn from(bytes: Bytes) -> Self {
+        bytes.0
+    }
+}
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Human-readable formats like JSON have no byte strings.
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&BASE64_STANDARD.encode(&self.0))
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let base64 = deserializer.is_human_readable();
+        deserializer.deserialize_byte_buf(BytesVisitor { base64 })
+    }
+}
+
+struct BytesVisitor {
+    // Whether strings are base64 in the format, instead of raw bytes.
+    base64: bool,
+}
+
+impl BytesVisitor {
+    fn decode<E: de::Error>(&self, bytes: Vec<u8>) -> Result<Bytes, E> {
+        if !self.base64 {
+            return Ok(Bytes(bytes));
+        }
+        BASE64_STANDARD.decode(bytes).map(Bytes).map_err(E::custom)
+    }
+}
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Bytes;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("bytes")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Bytes, E> {
+        self.decode(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Bytes, E> {
+        self.decode(bytes)
+    }
+
+    fn visit_str<E: de::Error>(self, string: &str) -> Result<Bytes, E> {
+        BASE64_STANDARD.decode(string).map(Bytes).map_err(E::custom)
+    }
+
+    // The encoding of a `Vec<u8>`.
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Bytes, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(Bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Compression, Encoding, evolution::check_compatible};
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Upload {
+        name: String,
+        data: Bytes,
+    }
+
+    #[test]
+    fn bytes_test() {
+        let upload = Upload {
+            name: "random".to_string(),
+            data: (0..=255).cycle().take(3000).collect::<Vec<u8>>().into(),
+        };
+
+        for &encoding in Encoding::ALL {
+            let encoded = encoding.encode(&upload, crate::Error::SerializeRequest).unwrap();
+            // JSON needs base64, every other codec stores the bytes as they are.
+            let limit = if encoding == Encoding::Json { 3000 * 4 / 3 + 64 } else { 3000 + 64 };
+            assert!(encoded.len() < limit, "{} encoded 3000 bytes in {}", encoding.name(), encoded.len());
+
+            let payload = crate::serde::encode_request_with(encoding, Compression::None, None, &upload).unwrap();
+            let decoded: Upload = crate::serde::decode_request(&payload).unwrap();
+            assert_eq!(upload, decoded);
+        }
+
+        // A `Vec<u8>` can be changed to `Bytes`.
+        #[derive(Serialize)]
+        struct OldUpload {
+            name: String,
+            data: Vec<u8>,
+        }
+        let old = OldUpload {
+            name: "random".to_string(),
+            data: vec![1, 2, 3],
+        };
+        let new: Upload = check_compatible(&old).unwrap();
+        assert_eq!(new.data.as_ref(), &[1, 2, 3]);
+    }
+}
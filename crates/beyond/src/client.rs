@@ -0,0 +1,282 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Compression, Encoding, Error, Framing, Progress, ResponseStream,
+    frame::{Frame, FrameKind},
+    serde::Headers,
+    ssh::{RemoteProcess, SSH},
+    transfer::{Transfer, collect_transfers},
+};
+
+/// How a client encodes its requests, and asks the server to encode its responses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Wire {
+    pub encoding: Encoding,
+    pub compression: Compression,
+    pub framing: Framing,
+}
+
+/// The files that were uploaded for a call.
+///
+/// They are deleted from the server when this is dropped.
+pub struct Uploads {
+    ssh: SSH,
+    // The temporary directory of the server, where the files are uploaded to.
+    temp_dir: PathBuf,
+    // The user the server process runs as through sudo, who has to be able to read the files.
+    run_as: Option<String>,
+    remote_paths: Vec<PathBuf>,
+}
+
+impl Uploads {
+    pub fn new(ssh: &SSH) -> Self {
+        Self {
+            ssh: ssh.clone(),
+            temp_dir: PathBuf::from("/tmp"),
+            run_as: None,
+            remote_paths: vec![],
+        }
+    }
+
+    /// Upload to the temporary directory the server reported in the handshake.
+    /// Servers that are too old to report it use `/tmp`.
+    pub fn with_temp_dir(mut self, temp_dir: Option<&str>) -> Self {
+        if let Some(temp_dir) = temp_dir {
+            self.temp_dir = PathBuf::from(temp_dir);
+        }
+        self
+    }
+
+    /// Upload the files as the user the server process runs as through sudo.
+    ///
+    /// sudo resets `TMPDIR`, so they go to `/tmp` instead of the temporary directory of the SSH user.
+    pub fn with_run_as(mut self, run_as: Option<&str>) -> Self {
+        if let Some(user) = run_as {
+            self.temp_dir = PathBuf::from("/tmp");
+            self.run_as = Some(user.to_string());
+        }
+        self
+    }
+}
+
+impl Drop for Uploads {
+    fn drop(&mut self) {
+        for remote_path in &self.remote_paths {
+            // The call is already over at this point, so a file
+            // that cannot be deleted is not worth failing for.
+            let _ = crate::transfer::remove(&self.ssh, remote_path, self.run_as.as_deref());
+        }
+    }
+}
+
+/// Get the headers of a single call from the headers of the client.
+///
+/// The request ID and client version are filled in if the caller did not set them.
+pub fn call_headers(headers: &Headers, client_version: &str) -> Headers {
+    let mut headers = headers.clone();
+    headers.request_id.get_or_insert_with(request_id);
+    headers.client_version.get_or_insert_with(|| client_version.to_string());
+    headers
+}
+
+/// Generate an ID that is unique enough to tell calls apart in logs.
+fn request_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut hasher = DefaultHasher::new();
+    std::process::id().hash(&mut hasher);
+    SystemTime::now().hash(&mut hasher);
+    COUNTER.fetch_add(1, Ordering::Relaxed).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Encode a request as text and upload the [`RemoteFile`](crate::RemoteFile)s it contains.
+pub fn encode_request<R: Serialize>(ssh: &SSH, wire: Wire, headers: &Headers, request: R, uploads: &mut Uploads) -> Result<String, Error> {
+    let (encoded_request, transfers) = collect_transfers(&uploads.temp_dir, || crate::serde::encode_request_with(wire.encoding, wire.compression, Some(headers), request));
+    let encoded_request = encoded_request?;
+    upload_transfers(ssh, transfers, uploads)?;
+    Ok(encoded_request)
+}
+
+/// Encode a request and write it to the stdin of the server process.
+pub fn write_request<W: Write, R: Serialize>(ssh: &SSH, input: &mut W, wire: Wire, headers: &Headers, request: R, uploads: &mut Uploads) -> Result<(), Error> {
+    match wire.framing {
+        Framing::Text => {
+            let encoded_request = encode_request(ssh, wire, headers, request, uploads)?;
+            writeln!(input, "{}", encoded_request).map_err(Error::SSHWriteStdin)
+        }
+        Framing::Binary => {
+            let (frame, transfers) = collect_transfers(&uploads.temp_dir, || crate::serde::encode_request_frame(wire.encoding, wire.compression, Some(headers), request));
+            let frame = frame?;
+            upload_transfers(ssh, transfers, uploads)?;
+            crate::frame::write_frame(input, &frame).map_err(Error::SSHWriteStdin)
+        }
+    }
+}
+
+fn upload_transfers(ssh: &SSH, transfers: Vec<Transfer>, uploads: &mut Uploads) -> Result<(), Error> {
+    for transfer in transfers {
+        crate::transfer::upload(ssh, &transfer.local, &transfer.remote, uploads.run_as.as_deref())?;
+        uploads.remote_paths.push(transfer.remote);
+    }
+    Ok(())
+}
+
+/// Start the server process with `command` for a route with a single request, and send the request.
+///
+/// With text framing, the request is appended to the command as an argument.
+/// With binary framing, it is written to stdin, which is closed afterwards.
+pub fn start_call<R: Serialize>(ssh: &SSH, command: &str, wire: Wire, headers: &Headers, request: R, uploads: &mut Uploads) -> Result<RemoteProcess, Error> {
+    match wire.framing {
+        Framing::Text => {
+            let encoded_request = encode_request(ssh, wire, headers, request, uploads)?;
+            ssh.execute_streaming(&format!("{} {}", command, encoded_request))
+        }
+        Framing::Binary => {
+            let mut process = ssh.spawn(command)?;
+            write_request(ssh, &mut process, wire, headers, request, uploads)?;
+            process.close_stdin()?;
+            Ok(process)
+        }
+    }
+}
+
+/// Decode a response and download the [`RemoteFile`](crate::RemoteFile)s it contains,
+/// as `run_as` if the server process runs as that user.
+///
+/// If the server spilled the response to a file, it is fetched and deleted first.
+/// The headers are `None` if the server is too old to send an envelope.
+pub fn decode_response<R: for<'a> Deserialize<'a>>(ssh: &SSH, run_as: Option<&str>, encoded_response: &str) -> Result<(R, Option<Headers>), Error> {
+    let encoded_response = match encoded_response.strip_prefix(crate::serde::SPILL_PREFIX) {
+        Some(path) => String::from_utf8(fetch_spilled_response(ssh, Path::new(path))?).map_err(Error::SpilledResponseUtf8)?,
+        None => encoded_response.to_string(),
+    };
+
+    let (marker, response) = crate::serde::payload_bytes(&encoded_response, Error::Base64DecodeResponse)?;
+    download_transfers(ssh, run_as, || crate::serde::decode_response_with_headers(marker, response))
+}
+
+/// Like [`decode_response`], but for a binary frame.
+pub fn decode_response_frame<R: for<'a> Deserialize<'a>>(ssh: &SSH, run_as: Option<&str>, frame: Frame) -> Result<(R, Option<Headers>), Error> {
+    let body = match frame.kind {
+        FrameKind::Spill => {
+            let path = String::from_utf8(frame.body).map_err(Error::SpilledResponseUtf8)?;
+            fetch_spilled_response(ssh, Path::new(&path))?
+        }
+        _ => frame.body,
+    };
+
+    download_transfers(ssh, run_as, || crate::serde::decode_response_with_headers(&frame.marker, body))
+}
+
+/// Run `decode` and download the [`RemoteFile`](crate::RemoteFile)s it decoded.
+fn download_transfers<R>(ssh: &SSH, run_as: Option<&str>, decode: impl FnOnce() -> Result<R, Error>) -> Result<R, Error> {
+    let (response, transfers) = collect_transfers(&std::env::temp_dir(), decode);
+    let response = response?;
+
+    for transfer in transfers {
+        crate::transfer::download(ssh, &transfer.remote, &transfer.local, run_as)?;
+    }
+
+    Ok(response)
+}
+
+/// The error for a server process that failed with `stderr`.
+pub(crate) fn process_error(stderr: &[u8], run_as: Option<&str>) -> Error {
+    let stderr = String::from_utf8_lossy(stderr).trim().to_string();
+    match run_as {
+        // sudo prefixes its own errors, which are reported before the server process starts.
+        Some(user) if stderr.starts_with("sudo:") => Error::SudoDenied {
+            user: user.to_string(),
+            message: stderr,
+        },
+        _ => Error::SSHProcessExecute { stderr },
+    }
+}
+
+fn fetch_spilled_response(ssh: &SSH, path: &Path) -> Result<Vec<u8>, Error> {
+    let contents = ssh.read(path);
+    // The file is only needed once, so a failure to delete it is not worth failing the call for.
+    let _ = ssh.remove(path);
+    contents
+}
+
+/// Decode the single response of a route and its headers, and wait for the server process to exit.
+///
+/// Progress updates that arrive before the response are passed to `on_progress`.
+/// The headers are `None` if the server is too old to send an envelope.
+pub fn receive_response<R: for<'a> Deserialize<'a>>(
+    ssh: SSH,
+    route: &'static str,
+    run_as: Option<&'static str>,
+    process: RemoteProcess,
+    uploads: Uploads,
+    on_progress: &mut dyn FnMut(Progress),
+) -> Result<(R, Option<Headers>), Error> {
+    finish_response(ResponseStream::new(ssh, route, run_as, process, uploads), on_progress)
+}
+
+/// Write the requests of a route that streams its requests to the stdin of the server process,
+/// then decode its single response and its headers and wait for the server process to exit.
+///
+/// stdout is read in the background while the requests are written, so that a server that logs or
+/// reports progress while it reads them cannot fill the SSH window and block both sides forever.
+/// Its log records and progress updates are handled between the requests.
+#[allow(clippy::too_many_arguments)]
+pub fn send_requests<Req: Serialize, R: for<'a> Deserialize<'a>>(
+    ssh: SSH,
+    route: &'static str,
+    run_as: Option<&'static str>,
+    process: RemoteProcess,
+    wire: Wire,
+    headers: &Headers,
+    requests: impl IntoIterator<Item = Req>,
+    uploads: Uploads,
+    on_progress: &mut dyn FnMut(Progress),
+) -> Result<(R, Option<Headers>), Error> {
+    let mut responses = ResponseStream::new_in_background(ssh.clone(), route, run_as, process, uploads);
+
+    // Send each request as soon as the iterator yields it, so that
+    // they never have to be held in memory all at once.
+    let mut write_result = Ok(());
+    for request in requests {
+        let Some((process, uploads)) = responses.input() else {
+            break;
+        };
+        write_result = write_request(&ssh, process, wire, headers, request, uploads);
+        if write_result.is_err() {
+            break;
+        }
+        responses.handle_received(on_progress);
+    }
+    if let Some((process, _)) = responses.input() {
+        process.close_stdin()?;
+    }
+
+    // If the server stopped early, its error is more useful than the write error,
+    // so the response is checked first.
+    let response = finish_response(responses, on_progress)?;
+    write_result?;
+
+    Ok(response)
+}
+
+fn finish_response<R: for<'a> Deserialize<'a>>(mut responses: ResponseStream<R>, on_progress: &mut dyn FnMut(Progress)) -> Result<(R, Option<Headers>), Error> {
+    let response = responses.next_with_progress(on_progress).unwrap_or(Err(Error::MissingResponse))?;
+    let headers = responses.headers().cloned();
+
+    // Drain the stream to find out if the server process succeeded.
+    for extra_response in responses {
+        extra_response?;
+    }
+
+    Ok((response, headers))
+}
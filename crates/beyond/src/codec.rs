@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// The environment variable that tells the server process which encoding to answer in.
+pub const CODEC_ENV: &str = "BEYOND_CODEC";
+
+/// A format that requests and responses can be encoded with.
+pub(crate) trait Codec {
+    /// The name that marks payloads encoded with this codec.
+    const NAME: &'static str;
+
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, Self::Error>;
+    fn decode<T: for<'a> Deserialize<'a>>(bytes: &[u8]) -> Result<T, Self::Error>;
+}
+
+pub(crate) struct Json;
+
+impl Codec for Json {
+    const NAME: &'static str = "json";
+
+    type Error = serde_json::Error;
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, Self::Error> {
+        serde_json::to_vec(value)
+    }
+
+    fn decode<T: for<'a> Deserialize<'a>>(bytes: &[u8]) -> Result<T, Self::Error> {
+        serde_json::from_slice(bytes)
+    }
+}
+
+#[cfg(feature = "msgpack")]
+pub(crate) struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    const NAME: &'static str = "msgpack";
+
+    // Encoding and decoding have different error types.
+    type Error = std::io::Error;
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, Self::Error> {
+        // Structs are encoded as maps, so that fields can be added later.
+        rmp_serde::to_vec_named(value).map_err(std::io::Error::other)
+    }
+
+    fn decode<T: for<'a> Deserialize<'a>>(bytes: &[u8]) -> Result<T, Self::Error> {
+        rmp_serde::from_slice(bytes).map_err(std::io::Error::other)
+    }
+}
+
+#[cfg(feature = "postcard")]
+pub(crate) struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    const NAME: &'static str = "postcard";
+
+    type Error = postcard::Error;
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, Self::Error> {
+        postcard::to_stdvec(value)
+    }
+
+    fn decode<T: for<'a> Deserialize<'a>>(bytes: &[u8]) -> Result<T, Self::Error> {
+        postcard::from_bytes(bytes)
+    }
+}
+
+#[cfg(feature = "cbor")]
+pub(crate) struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    const NAME: &'static str = "cbor";
+
+    // Encoding and decoding have different error types.
+    type Error = std::io::Error;
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, Self::Error> {
+        let mut bytes = vec![];
+        ciborium::into_writer(value, &mut bytes).map_err(std::io::Error::other)?;
+        Ok(bytes)
+    }
+
+    fn decode<T: for<'a> Deserialize<'a>>(bytes: &[u8]) -> Result<T, Self::Error> {
+        ciborium::from_reader(bytes).map_err(std::io::Error::other)
+    }
+}
+
+/// The codec a client encodes its requests with, set with `Client::set_encoding`.
+///
+/// Calls use JSON instead if the server binary does not support the encoding, which it
+/// reports in the handshake. Every encoding except JSON needs the cargo feature of the
+/// same name on both sides.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "postcard")]
+    Postcard,
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl Encoding {
+    /// All encodings that are enabled.
+    pub const ALL: &'static [Encoding] = &[
+        Encoding::Json,
+        #[cfg(feature = "msgpack")]
+        Encoding::MessagePack,
+        #[cfg(feature = "postcard")]
+        Encoding::Postcard,
+        #[cfg(feature = "cbor")]
+        Encoding::Cbor,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Json => Json::NAME,
+            #[cfg(feature = "msgpack")]
+            Encoding::MessagePack => MessagePack::NAME,
+            #[cfg(feature = "postcard")]
+            Encoding::Postcard => Postcard::NAME,
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => Cbor::NAME,
+        }
+    }
+
+    /// The enabled encoding with the given name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|encoding| encoding.name() == name)
+    }
+
+    /// The encoding the client asked the server process to answer in.
+    pub fn from_env() -> Self {
+        std::env::var(CODEC_ENV).ok().and_then(|name| Self::from_name(&name)).unwrap_or_default()
+    }
+
+    /// Whether payloads contain the names of the fields, so that fields can be added
+    /// and removed as described in [`evolution`](crate::evolution).
+    pub fn is_self_describing(self) -> bool {
+        // Postcard only writes the values, in the order of the fields.
+        #[cfg(feature = "postcard")]
+        if self == Encoding::Postcard {
+            return false;
+        }
+        true
+    }
+
+    /// Encode `value`. JSON errors are mapped with `json_error` to keep their specific variants.
+    pub(crate) fn encode<T: Serialize>(self, value: &T, json_error: fn(serde_json::Error) -> Error) -> Result<Vec<u8>, Error> {
+        match self {
+            Encoding::Json => Json::encode(value).map_err(json_error),
+            #[cfg(feature = "msgpack")]
+            Encoding::MessagePack => MessagePack::encode(value).map_err(codec_error::<MessagePack>),
+            #[cfg(feature = "postcard")]
+            Encoding::Postcard => Postcard::encode(value).map_err(codec_error::<Postcard>),
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => Cbor::encode(value).map_err(codec_error::<Cbor>),
+        }
+    }
+
+    /// Decode `bytes`. JSON errors are mapped with `json_error` to keep their specific variants.
+    pub(crate) fn decode<T: for<'a> Deserialize<'a>>(self, bytes: &[u8], json_error: fn(serde_json::Error) -> Error) -> Result<T, Error> {
+        match self {
+            Encoding::Json => Json::decode(bytes).map_err(json_error),
+            #[cfg(feature = "msgpack")]
+            Encoding::MessagePack => MessagePack::decode(bytes).map_err(codec_error::<MessagePack>),
+            #[cfg(feature = "postcard")]
+            Encoding::Postcard => Postcard::decode(bytes).map_err(codec_error::<Postcard>),
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => Cbor::decode(bytes).map_err(codec_error::<Cbor>),
+        }
+    }
+}
+
+#[cfg(any(feature = "msgpack", feature = "postcard", feature = "cbor"))]
+fn codec_error<C: Codec>(e: C::Error) -> Error {
+    Error::Codec {
+        codec: C::NAME,
+        source: Box::new(e),
+    }
+}
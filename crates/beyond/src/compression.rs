@@ -0,0 +1,104 @@
+#[cfg(feature = "gzip")]
+use std::io::{Read, Write};
+
+use crate::Error;
+
+/// The environment variable that tells the server process which compression to use for its responses.
+pub const COMPRESSION_ENV: &str = "BEYOND_COMPRESSION";
+
+/// Encoded payloads smaller than this are never compressed, since it would not pay off.
+pub const COMPRESSION_THRESHOLD: usize = 4 * 1024;
+
+/// How payloads above [`COMPRESSION_THRESHOLD`] are compressed, set with `Client::set_compression`.
+///
+/// The server compresses its responses the same way. Calls are not compressed if the
+/// server binary does not support the compression, which it reports in the handshake.
+/// Every compression needs the cargo feature of the same name on both sides.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    #[cfg(feature = "zstd")]
+    Zstd,
+    #[cfg(feature = "gzip")]
+    Gzip,
+}
+
+impl Compression {
+    /// All compressions that are enabled.
+    pub const ALL: &'static [Compression] = &[
+        Compression::None,
+        #[cfg(feature = "zstd")]
+        Compression::Zstd,
+        #[cfg(feature = "gzip")]
+        Compression::Gzip,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => "zstd",
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => "gzip",
+        }
+    }
+
+    /// The enabled compression with the given name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|compression| compression.name() == name)
+    }
+
+    /// The compression the client asked the server process to use.
+    pub fn from_env() -> Self {
+        std::env::var(COMPRESSION_ENV).ok().and_then(|name| Self::from_name(&name)).unwrap_or_default()
+    }
+
+    /// Compress `bytes` if they are large enough and get smaller, and return the compression that was used.
+    pub(crate) fn compress(self, bytes: Vec<u8>) -> Result<(Compression, Vec<u8>), Error> {
+        if bytes.len() < COMPRESSION_THRESHOLD {
+            return Ok((Compression::None, bytes));
+        }
+
+        let compressed: Option<Vec<u8>> = match self {
+            Compression::None => None,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Some(zstd::encode_all(bytes.as_slice(), 0).map_err(|e| compression_error(self, e))?),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(&bytes).map_err(|e| compression_error(self, e))?;
+                Some(encoder.finish().map_err(|e| compression_error(self, e))?)
+            }
+        };
+
+        match compressed {
+            Some(compressed) if compressed.len() < bytes.len() => Ok((self, compressed)),
+            _ => Ok((Compression::None, bytes)),
+        }
+    }
+
+    pub(crate) fn decompress(self, bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+        match self {
+            Compression::None => Ok(bytes),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::decode_all(bytes.as_slice()).map_err(|e| compression_error(self, e)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut decompressed = vec![];
+                flate2::read::GzDecoder::new(bytes.as_slice())
+                    .read_to_end(&mut decompressed)
+                    .map_err(|e| compression_error(self, e))?;
+                Ok(decompressed)
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "zstd", feature = "gzip"))]
+fn compression_error(compression: Compression, e: std::io::Error) -> Error {
+    Error::Compression {
+        compression: compression.name(),
+        source: e,
+    }
+}
@@ -0,0 +1,263 @@
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::{Error, exec::shell_quote, ssh::SSH};
+
+/// How `Client::deploy` installs the server binary.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeployOptions {
+    artifact: Option<PathBuf>,
+    artifact_dir: Option<PathBuf>,
+    remote_path: Option<PathBuf>,
+    auto_deploy: bool,
+}
+
+impl DeployOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Upload `artifact` instead of the current executable.
+    pub fn artifact(mut self, artifact: impl Into<PathBuf>) -> Self {
+        self.artifact = Some(artifact.into());
+        self
+    }
+
+    /// Pick the artifact for the server from a directory of builds for different targets.
+    ///
+    /// The server is probed for its architecture, OS and libc, and the artifact is
+    /// looked up at `<dir>/<target triple>/<name>` or `<dir>/<target triple>/release/<name>`,
+    /// where `<name>` is the file name of the current executable. This means cargo's
+    /// `target` directory can be used after `cargo build --release --target <triple>`.
+    pub fn artifact_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.artifact_dir = Some(dir.into());
+        self
+    }
+
+    /// Install the server binary at `remote_path` instead of the path the client invokes.
+    ///
+    /// Relative paths are relative to the home directory of the SSH user. This is required
+    /// if the server binary is invoked by a bare name that is not on the `PATH` of the server yet.
+    pub fn remote_path(mut self, remote_path: impl Into<PathBuf>) -> Self {
+        self.remote_path = Some(remote_path.into());
+        self
+    }
+
+    /// Deploy the server binary from `Client::check_server` if it is missing or outdated.
+    pub fn auto_deploy(mut self, auto_deploy: bool) -> Self {
+        self.auto_deploy = auto_deploy;
+        self
+    }
+
+    pub fn is_auto_deploy(&self) -> bool {
+        self.auto_deploy
+    }
+}
+
+/// Upload the artifact to the server, make it executable and verify its hash.
+///
+/// The artifact is uploaded next to its final path first, verified, and only then
+/// moved into place with `mv`, which replaces the old binary in one step. A running
+/// server process is never overwritten halfway, and a broken upload never replaces
+/// a working server. On servers without `mv`, the old binary is removed before the
+/// new one is moved into place. The upload is removed again if anything fails.
+pub fn deploy(ssh: &SSH, options: &DeployOptions, server_binary: &str) -> Result<(), Error> {
+    let artifact = artifact_path(ssh, options)?;
+    let remote_path = remote_path(ssh, options, server_binary)?;
+    deploy_to(ssh, &artifact, &remote_path)
+}
+
+/// Deploy the artifact unless the server binary on the server already matches it.
+pub fn ensure_deployed(ssh: &SSH, options: &DeployOptions, server_binary: &str) -> Result<(), Error> {
+    let artifact = artifact_path(ssh, options)?;
+    let remote_path = remote_path(ssh, options, server_binary)?;
+
+    if remote_hash(ssh, &remote_path)? == Some(local_hash(&artifact)?) {
+        return Ok(());
+    }
+
+    deploy_to(ssh, &artifact, &remote_path)
+}
+
+fn deploy_to(ssh: &SSH, artifact: &Path, remote_path: &Path) -> Result<(), Error> {
+    let mut upload_path = remote_path.to_path_buf().into_os_string();
+    upload_path.push(".beyond-upload");
+    let upload_path = PathBuf::from(upload_path);
+
+    let result = install(ssh, artifact, &upload_path, remote_path);
+    if result.is_err() {
+        // It may not exist, e.g. if the upload failed, and the deploy already failed anyway.
+        let _ = ssh.remove(&upload_path);
+    }
+    result
+}
+
+/// Upload the artifact to `upload_path`, verify it and move it to `remote_path`.
+fn install(ssh: &SSH, artifact: &Path, upload_path: &Path, remote_path: &Path) -> Result<(), Error> {
+    ssh.upload(artifact, upload_path)?;
+    ssh.set_permissions(upload_path, 0o755)?;
+
+    if remote_hash(ssh, upload_path)? != Some(local_hash(artifact)?) {
+        return Err(Error::DeployVerify {
+            path: remote_path.display().to_string(),
+        });
+    }
+
+    let mv = format!("mv -f -- {} {}", shell_quote(&upload_path.to_string_lossy()), shell_quote(&remote_path.to_string_lossy()));
+    if !ssh.execute(&mv)?.status.success() {
+        ssh.rename(upload_path, remote_path)?;
+    }
+
+    Ok(())
+}
+
+fn artifact_path(ssh: &SSH, options: &DeployOptions) -> Result<PathBuf, Error> {
+    if let Some(artifact) = &options.artifact {
+        return Ok(artifact.clone());
+    }
+
+    let current_exe = std::env::current_exe().map_err(Error::DeployArtifact)?;
+    let Some(artifact_dir) = &options.artifact_dir else {
+        return Ok(current_exe);
+    };
+
+    let name = current_exe.file_name().unwrap_or_default();
+    let targets = probe_targets(ssh)?;
+    for target in &targets {
+        for candidate in [artifact_dir.join(target).join(name), artifact_dir.join(target).join("release").join(name)] {
+            if candidate.is_file() {
+                return Ok(candidate);
+            }
+        }
+    }
+
+    Err(Error::DeployNoArtifact {
+        dir: artifact_dir.display().to_string(),
+        targets,
+    })
+}
+
+/// Find out which target triples can run on the server, the best match first.
+fn probe_targets(ssh: &SSH) -> Result<Vec<String>, Error> {
+    let uname = ssh.execute("uname -sm")?;
+    if !uname.status.success() {
+        return Err(Error::SSHProcessExecute {
+            stderr: String::from_utf8_lossy(&uname.stderr).trim().to_string(),
+        });
+    }
+
+    // musl's `ldd` prints its version to stderr and fails, so only the output matters.
+    let ldd = ssh.execute("ldd --version 2>&1")?;
+
+    Ok(target_triples(&String::from_utf8_lossy(&uname.stdout), &String::from_utf8_lossy(&ldd.stdout)))
+}
+
+/// Map the output of `uname -sm` and `ldd --version` to target triples.
+fn target_triples(uname: &str, ldd: &str) -> Vec<String> {
+    let mut parts = uname.split_whitespace();
+    let os = parts.next().unwrap_or_default();
+    let arch = match parts.next().unwrap_or_default() {
+        "arm64" => "aarch64",
+        "amd64" => "x86_64",
+        "i386" | "i486" | "i586" => "i686",
+        arch => arch,
+    };
+
+    match os {
+        "Darwin" => vec![format!("{}-apple-darwin", arch)],
+        "Linux" => {
+            // 32-bit ARM uses the hard-float ABI suffix.
+            let abi_suffix = if arch.starts_with("armv7") { "eabihf" } else { "" };
+            let arch = if arch.starts_with("armv7") { "armv7" } else { arch };
+            let musl = format!("{}-unknown-linux-musl{}", arch, abi_suffix);
+
+            if ldd.to_lowercase().contains("musl") {
+                vec![musl]
+            } else {
+                // Statically linked musl builds also run on glibc systems.
+                vec![format!("{}-unknown-linux-gnu{}", arch, abi_suffix), musl]
+            }
+        }
+        _ => vec![format!("{}-unknown-{}", arch, os.to_lowercase())],
+    }
+}
+
+/// The path of the server binary that the client invokes.
+///
+/// A bare name is looked up on the `PATH` of the server, like the client's calls do.
+fn remote_path(ssh: &SSH, options: &DeployOptions, server_binary: &str) -> Result<PathBuf, Error> {
+    if let Some(remote_path) = &options.remote_path {
+        return Ok(remote_path.clone());
+    }
+    if let Some(relative_path) = server_binary.strip_prefix("~/") {
+        // SFTP resolves relative paths in the home directory, like the shell resolves `~`.
+        return Ok(PathBuf::from(relative_path));
+    }
+    if server_binary.contains('/') {
+        return Ok(PathBuf::from(server_binary));
+    }
+
+    let output = ssh.execute(&format!("command -v {}", shell_quote(server_binary)))?;
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    // Aliases and functions are printed without a path.
+    if !output.status.success() || !path.starts_with('/') {
+        return Err(Error::DeployRemotePath {
+            server_binary: server_binary.to_string(),
+        });
+    }
+
+    Ok(PathBuf::from(path))
+}
+
+/// The SHA-256 hash of a local file as a hex string.
+fn local_hash(path: &Path) -> Result<String, Error> {
+    let contents = std::fs::read(path).map_err(Error::DeployArtifact)?;
+    Ok(to_hex(&Sha256::digest(&contents)))
+}
+
+/// The SHA-256 hash of a file on the server as a hex string, or `None` if it does not exist.
+fn remote_hash(ssh: &SSH, path: &Path) -> Result<Option<String>, Error> {
+    let path = shell_quote(&path.to_string_lossy());
+    // macOS does not ship `sha256sum`, but `shasum` instead.
+    let output = ssh.execute(&format!("sha256sum -- {0} 2>/dev/null || shasum -a 256 -- {0} 2>/dev/null", path))?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).split_whitespace().next().map(str::to_lowercase))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_triples_test() {
+        assert_eq!(
+            target_triples("Linux x86_64\n", "ldd (GNU libc) 2.36\n"),
+            vec!["x86_64-unknown-linux-gnu", "x86_64-unknown-linux-musl"]
+        );
+        assert_eq!(target_triples("Linux aarch64\n", "musl libc (aarch64)\n"), vec!["aarch64-unknown-linux-musl"]);
+        assert_eq!(
+            target_triples("Linux armv7l\n", ""),
+            vec!["armv7-unknown-linux-gnueabihf", "armv7-unknown-linux-musleabihf"]
+        );
+        assert_eq!(target_triples("Darwin arm64\n", ""), vec!["aarch64-apple-darwin"]);
+    }
+
+    #[test]
+    fn local_hash_test() {
+        let path = std::env::temp_dir().join(crate::transfer::temporary_file_name(Path::new("artifact")));
+        std::fs::write(&path, "beyond").unwrap();
+        let hash = local_hash(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The same as the output of `printf beyond | sha256sum`.
+        assert_eq!(hash, "5e8818d1dd191490273376c09764e7c1b1c5403c815cbf08088bdabdfe61000f");
+    }
+}
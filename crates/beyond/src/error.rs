@@ -10,38 +10,98 @@ pub enum Error {
     /// The client failed to deserialize a response.
     DeserializeResponse(serde_json::Error),
 
+    /// The server failed to read a streamed request from stdin.
+    ReadRequest(std::io::Error),
+    /// The server failed to write the response to stdout.
+    WriteResponse(std::io::Error),
+
     /// The server failed to decode the base64-form of the request.
     Base64DecodeRequest(base64::DecodeError),
     /// The client failed to decode the base64-form of the response.
     Base64DecodeResponse(base64::DecodeError),
+    /// The path of a spilled response, or the text in the file, is not valid UTF-8.
+    SpilledResponseUtf8(std::string::FromUtf8Error),
 
     /// Failed to create an SSH session.
-    SSHSessionCreate,
-    /// Failed to the the host for the SSH session.
-    SSHSetHost(ssh::Error),
-    /// Failed to parse the local SSH config.
-    SSHConfigParse(ssh::Error),
+    SSHSessionCreate(ssh2::Error),
+    /// Failed to open the TCP connection to the host.
+    SSHTcpConnect(std::io::Error),
+    /// Failed to read the local SSH config.
+    SSHConfigParse(std::io::Error),
     /// Failed to connect to the server over SSH.
-    SSHConnect(ssh::Error),
+    SSHConnect(ssh2::Error),
+    /// The host key of the server does not match the one in `~/.ssh/known_hosts`.
+    SSHHostKeyMismatch { host: String },
+    /// The host is not in `~/.ssh/known_hosts` and the host key policy is strict.
+    SSHHostKeyUnknown { host: String },
+    /// The host key of the server could not be checked against the known hosts.
+    SSHHostKeyUnverified { host: String },
+    /// Failed to read or write the known hosts file at `path`.
+    SSHKnownHosts { path: String, source: std::io::Error },
     /// Failed to authenticate on the server.
-    SSHAuth(ssh::Error),
+    SSHAuth(ssh2::Error),
+    /// Failed to open a tunnel to the next host through a jump host.
+    SSHTunnel(ssh2::Error),
     /// Failed to create the SSH channel to execute the command.
-    SSHChannelCreate(ssh::Error),
-    /// Failed to open the SSH channel to execute the command.
-    SSHChannelOpen(ssh::Error),
+    SSHChannelCreate(ssh2::Error),
     /// Failed to execute the command over SSH.
-    SSHExecute(ssh::Error),
+    SSHExecute(ssh2::Error),
     /// The command was stopped by a signal on the server.
     SSHCommandStoppedBySignal,
     /// Failed to read stdout from SSH.
     SSHReadStdout(std::io::Error),
     /// Failed to read stderr from SSH.
     SSHReadStderr(std::io::Error),
+    /// Failed to write to stdin over SSH.
+    SSHWriteStdin(std::io::Error),
+    /// The server process exited with a failure.
+    SSHProcessExecute { stderr: String },
+    /// sudo refused to run the server process as `user`, e.g. because a password would be required.
+    SudoDenied { user: String, message: String },
+    /// Failed to start SFTP or to access a file on the server.
+    SSHSftp(ssh2::Error),
+    /// Failed to copy a file between the client and the server.
+    SSHFileTransfer(std::io::Error),
 
     /// The requested route does not exist.
     InvalidRoute { route_name: String },
+    /// An environment variable for the server process has an invalid name.
+    InvalidEnvironmentVariable { name: String },
     /// The server component is not installed on the server.
     ServerComponentNotInstalled,
+    /// The server binary exists, but cannot be run, e.g. because it is not executable or a library is missing.
+    ServerNotExecutable { message: String },
+    /// The server binary is not a `beyond` server.
+    ServerWrongProgram { message: String },
+    /// The server binary speaks a different protocol version than the client.
+    /// `server` is `None` if it is too old to take part in the handshake.
+    IncompatibleProtocol { client: u32, server: Option<u32> },
+    /// The server binary does not have the route, or has it with different request or response types.
+    IncompatibleRoute { route: String },
+    /// A payload was encoded with a codec that is not enabled.
+    UnsupportedCodec { codec: String },
+    /// A payload was wrapped in a version of the envelope that this side does not know.
+    UnsupportedEnvelope { version: String },
+    /// A codec other than JSON failed to encode or decode a payload.
+    Codec { codec: &'static str, source: Box<dyn std::error::Error + Send + Sync> },
+    /// A payload was compressed with a compression that is not enabled.
+    UnsupportedCompression { compression: String },
+    /// Failed to compress or decompress a payload.
+    Compression { compression: &'static str, source: std::io::Error },
+    /// Failed to read the server binary that should be deployed.
+    DeployArtifact(std::io::Error),
+    /// The uploaded server binary does not match the artifact, so it did not replace the one at `path`.
+    DeployVerify { path: String },
+    /// None of the artifacts for different targets can run on the server.
+    DeployNoArtifact { dir: String, targets: Vec<String> },
+    /// The server binary is invoked by a name that is not on the `PATH` of the server, so it is unclear where to deploy it.
+    DeployRemotePath { server_binary: String },
+    /// The server process exited without sending a response.
+    MissingResponse,
+    /// Failed to read or write a file with recorded payloads, see [`evolution::check_fixture`](crate::evolution::check_fixture).
+    Fixture { path: String, source: std::io::Error },
+    /// Work was distributed across a fleet without any hosts.
+    EmptyFleet,
 }
 
 impl std::fmt::Display for Error {
@@ -52,23 +112,54 @@ impl std::fmt::Display for Error {
             Error::DeserializeRequest(e) => write!(f, "failed to deserialize the request: {}", e),
             Error::DeserializeResponse(e) => write!(f, "failed to deserialize the response: {}", e),
 
+            Error::ReadRequest(e) => write!(f, "failed to read the request: {}", e),
+            Error::WriteResponse(e) => write!(f, "failed to write the response: {}", e),
+
             Error::Base64DecodeRequest(e) => write!(f, "failed to decode the request from base 64: {}", e),
             Error::Base64DecodeResponse(e) => write!(f, "failed to decode the response from base 64: {}", e),
+            Error::SpilledResponseUtf8(e) => write!(f, "the spilled response is not valid UTF-8: {}", e),
 
-            Error::SSHSessionCreate => write!(f, "failed to create the ssh session"),
-            Error::SSHSetHost(e) => write!(f, "failed to set the host for ssh: {}", e),
-            Error::SSHConfigParse(e) => write!(f, "failed to parse the ssh config: {}", e),
+            Error::SSHSessionCreate(e) => write!(f, "failed to create the ssh session: {}", e),
+            Error::SSHTcpConnect(e) => write!(f, "failed to connect to the host: {}", e),
+            Error::SSHConfigParse(e) => write!(f, "failed to read the ssh config: {}", e),
             Error::SSHConnect(e) => write!(f, "ssh failed to connect: {}", e),
+            Error::SSHHostKeyMismatch { host } => write!(f, "the host key of '{}' does not match the known hosts", host),
+            Error::SSHHostKeyUnknown { host } => write!(f, "the host key of '{}' is not in the known hosts", host),
+            Error::SSHHostKeyUnverified { host } => write!(f, "the host key of '{}' could not be checked", host),
+            Error::SSHKnownHosts { path, source } => write!(f, "failed to use the known hosts file '{}': {}", path, source),
             Error::SSHAuth(e) => write!(f, "ssh authentication failed: {}", e),
+            Error::SSHTunnel(e) => write!(f, "failed to open a tunnel through the jump host: {}", e),
             Error::SSHChannelCreate(e) => write!(f, "failed to create an ssh channel: {}", e),
-            Error::SSHChannelOpen(e) => write!(f, "failed to open the session on the ssh channel: {}", e),
             Error::SSHExecute(e) => write!(f, "failed to execute the command over ssh: {}", e),
             Error::SSHCommandStoppedBySignal => write!(f, "the command executed over ssh was stopped by a signal"),
             Error::SSHReadStdout(e) => write!(f, "failed to read stdout over ssh: {}", e),
             Error::SSHReadStderr(e) => write!(f, "failed to read stderr over ssh: {}", e),
+            Error::SSHWriteStdin(e) => write!(f, "failed to write stdin over ssh: {}", e),
+            Error::SSHProcessExecute { stderr } => write!(f, "the server process failed: {}", stderr),
+            Error::SudoDenied { user, message } => write!(f, "sudo refused to run the server process as '{}': {}", user, message),
+            Error::SSHSftp(e) => write!(f, "sftp failed: {}", e),
+            Error::SSHFileTransfer(e) => write!(f, "failed to transfer a file: {}", e),
 
             Error::InvalidRoute { route_name } => write!(f, "'{}' is not a valid route", route_name),
+            Error::InvalidEnvironmentVariable { name } => write!(f, "'{}' is not a valid environment variable name", name),
             Error::ServerComponentNotInstalled => write!(f, "the server component is not installed on the server"),
+            Error::ServerNotExecutable { message } => write!(f, "the server component cannot be executed: {}", message),
+            Error::ServerWrongProgram { message } => write!(f, "the server component is not a beyond server: {}", message),
+            Error::IncompatibleProtocol { client, server: Some(server) } => write!(f, "the server speaks protocol version {} but the client speaks version {}", server, client),
+            Error::IncompatibleProtocol { client, server: None } => write!(f, "the server is too old for the client, which speaks protocol version {}", client),
+            Error::IncompatibleRoute { route } => write!(f, "the route '{}' of the server binary does not match the client", route),
+            Error::UnsupportedCodec { codec } => write!(f, "the codec '{}' is not supported, enable its feature of beyond", codec),
+            Error::UnsupportedEnvelope { version } => write!(f, "the envelope version '{}' is not supported, update beyond", version),
+            Error::Codec { codec, source } => write!(f, "the {} codec failed: {}", codec, source),
+            Error::UnsupportedCompression { compression } => write!(f, "the compression '{}' is not supported, enable its feature of beyond", compression),
+            Error::Compression { compression, source } => write!(f, "the {} compression failed: {}", compression, source),
+            Error::DeployArtifact(e) => write!(f, "failed to read the server binary to deploy: {}", e),
+            Error::DeployVerify { path } => write!(f, "the server binary uploaded for '{}' does not match the artifact", path),
+            Error::DeployNoArtifact { dir, targets } => write!(f, "'{}' contains no server binary for the server's target ({})", dir, targets.join(", ")),
+            Error::DeployRemotePath { server_binary } => write!(f, "'{}' is not on the PATH of the server, set a remote path in the deploy options", server_binary),
+            Error::MissingResponse => write!(f, "the server process did not send a response"),
+            Error::Fixture { path, source } => write!(f, "failed to read or write the fixture '{}': {}", path, source),
+            Error::EmptyFleet => write!(f, "the fleet does not contain any hosts"),
         }
     }
 }
@@ -81,23 +172,53 @@ impl std::error::Error for Error {
             Error::DeserializeRequest(e) => Some(e),
             Error::DeserializeResponse(e) => Some(e),
 
+            Error::ReadRequest(e) => Some(e),
+            Error::WriteResponse(e) => Some(e),
+
             Error::Base64DecodeRequest(e) => Some(e),
             Error::Base64DecodeResponse(e) => Some(e),
+            Error::SpilledResponseUtf8(e) => Some(e),
 
-            Error::SSHSessionCreate => None,
-            Error::SSHSetHost(e) => Some(e),
+            Error::SSHSessionCreate(e) => Some(e),
+            Error::SSHTcpConnect(e) => Some(e),
             Error::SSHConfigParse(e) => Some(e),
             Error::SSHConnect(e) => Some(e),
+            Error::SSHHostKeyMismatch { host: _ } => None,
+            Error::SSHHostKeyUnknown { host: _ } => None,
+            Error::SSHHostKeyUnverified { host: _ } => None,
+            Error::SSHKnownHosts { path: _, source } => Some(source),
             Error::SSHAuth(e) => Some(e),
+            Error::SSHTunnel(e) => Some(e),
             Error::SSHChannelCreate(e) => Some(e),
-            Error::SSHChannelOpen(e) => Some(e),
             Error::SSHExecute(e) => Some(e),
             Error::SSHCommandStoppedBySignal => None,
             Error::SSHReadStdout(e) => Some(e),
             Error::SSHReadStderr(e) => Some(e),
+            Error::SSHWriteStdin(e) => Some(e),
+            Error::SSHProcessExecute { stderr: _ } => None,
+            Error::SudoDenied { user: _, message: _ } => None,
+            Error::SSHSftp(e) => Some(e),
+            Error::SSHFileTransfer(e) => Some(e),
 
             Error::InvalidRoute { route_name: _ } => None,
+            Error::InvalidEnvironmentVariable { name: _ } => None,
             Error::ServerComponentNotInstalled => None,
+            Error::ServerNotExecutable { message: _ } => None,
+            Error::ServerWrongProgram { message: _ } => None,
+            Error::IncompatibleProtocol { client: _, server: _ } => None,
+            Error::IncompatibleRoute { route: _ } => None,
+            Error::UnsupportedCodec { codec: _ } => None,
+            Error::UnsupportedEnvelope { version: _ } => None,
+            Error::Codec { codec: _, source } => Some(source.as_ref()),
+            Error::UnsupportedCompression { compression: _ } => None,
+            Error::Compression { compression: _, source } => Some(source),
+            Error::DeployArtifact(e) => Some(e),
+            Error::DeployVerify { path: _ } => None,
+            Error::DeployNoArtifact { dir: _, targets: _ } => None,
+            Error::DeployRemotePath { server_binary: _ } => None,
+            Error::MissingResponse => None,
+            Error::Fixture { path: _, source } => Some(source),
+            Error::EmptyFleet => None,
         }
     }
 }
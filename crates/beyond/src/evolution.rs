@@ -0,0 +1,191 @@
+//! Changing request and response types without upgrading every client and server at once.
+//!
+//! The client and the servers of a fleet are rarely upgraded at the same time, so a
+//! newer side has to read the payloads of an older side and the other way around.
+//! This works for a type as long as its changes follow these rules:
+//!
+//! - New fields have `#[serde(default)]`, so that payloads from older sides without them still decode.
+//! - Fields are only removed if they had `#[serde(default)]` in every version still in use.
+//! - Fields are never renamed, since older sides would miss them. `#[serde(alias = "old_name")]`
+//!   only lets newer sides read the old name.
+//! - Types never use `#[serde(deny_unknown_fields)]`, so that older sides skip the fields they do not know.
+//! - The type of a field never changes. Add a new field instead. The only exception is a
+//!   `Vec<u8>` that becomes [`Bytes`](crate::Bytes), after every side that reads it is upgraded.
+//! - New enum variants are only sent once every side knows them. Requests that change
+//!   fundamentally are enums with one variant per version from the start, see [`Versioned`].
+//! - Only self-describing encodings are used, see [`Encoding::is_self_describing`].
+//!   Postcard payloads cannot gain or lose fields.
+//!
+//! Since the fingerprint of a type changes with every field, the route has to be marked with
+//! the `evolving` option, e.g. `#[beyond_route(hello HelloRequest HelloResponse, evolving)]`.
+//! The client then only warns about different types on the server instead of refusing the call.
+//!
+//! [`check_compatible`] and [`check_fixture`] check in tests that a type follows the rules.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Compression, Encoding, Error, serde::Headers};
+
+/// A request or response with one variant per version, e.g.
+/// `enum GreetRequest { V1(GreetRequestV1), V2(GreetRequestV2) }`.
+///
+/// New variants are added at the end, and handlers call [`Versioned::into_latest`]
+/// instead of matching on the versions.
+pub trait Versioned {
+    type Latest;
+
+    fn into_latest(self) -> Self::Latest;
+}
+
+/// Check that `value` still decodes after it was encoded, as `To`, and return it.
+///
+/// Pass the old type as `From` and the new type as `To` to check that newer sides read
+/// older payloads, and the other way around to check that older sides read newer payloads.
+/// Every enabled self-describing encoding is checked, and the JSON result is returned.
+pub fn check_compatible<From: Serialize, To: for<'de> Deserialize<'de>>(value: &From) -> Result<To, Error> {
+    let mut decoded = None;
+    for encoding in self_describing_encodings() {
+        let payload = crate::serde::encode_request_with(encoding, Compression::None, Some(&Headers::new()), value)?;
+        let value = crate::serde::decode_request(&payload)?;
+        decoded.get_or_insert(value);
+    }
+
+    Ok(decoded.expect("JSON is always enabled and self-describing"))
+}
+
+/// Check that the payloads recorded in the file at `path` still decode as `T`, and return them.
+///
+/// If the file does not exist yet, `current` is recorded in every enabled self-describing
+/// encoding first. Committing the file makes sure that later versions of `T` are checked
+/// against the payloads of this version. Payloads in encodings that are not enabled are skipped.
+pub fn check_fixture<T: Serialize + for<'de> Deserialize<'de>>(path: impl AsRef<Path>, current: &T) -> Result<Vec<T>, Error> {
+    let path = path.as_ref();
+    let fixture_error = |source: std::io::Error| Error::Fixture {
+        path: path.display().to_string(),
+        source,
+    };
+
+    if !path.exists() {
+        let mut payloads = String::new();
+        for encoding in self_describing_encodings() {
+            payloads.push_str(&crate::serde::encode_request_with(encoding, Compression::None, Some(&Headers::new()), current)?);
+            payloads.push('\n');
+        }
+        std::fs::write(path, payloads).map_err(fixture_error)?;
+    }
+
+    std::fs::read_to_string(path)
+        .map_err(fixture_error)?
+        .lines()
+        .filter(|payload| !payload.trim().is_empty())
+        .filter_map(|payload| match crate::serde::decode_request(payload.trim()) {
+            Err(Error::UnsupportedCodec { codec: _ } | Error::UnsupportedCompression { compression: _ }) => None,
+            result => Some(result),
+        })
+        .collect()
+}
+
+fn self_describing_encodings() -> impl Iterator<Item = Encoding> {
+    Encoding::ALL.iter().copied().filter(|encoding| encoding.is_self_describing())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+    struct GreetRequestV1 {
+        name: String,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+    struct GreetRequestV2 {
+        name: String,
+        #[serde(default)]
+        greeting: Option<String>,
+        #[serde(default)]
+        tags: BTreeMap<String, String>,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+    enum GreetRequest {
+        V1(GreetRequestV1),
+        V2(GreetRequestV2),
+    }
+
+    impl Versioned for GreetRequest {
+        type Latest = GreetRequestV2;
+
+        fn into_latest(self) -> GreetRequestV2 {
+            match self {
+                GreetRequest::V1(request) => GreetRequestV2 {
+                    name: request.name,
+                    greeting: None,
+                    tags: BTreeMap::new(),
+                },
+                GreetRequest::V2(request) => request,
+            }
+        }
+    }
+
+    #[test]
+    fn check_compatible_test() {
+        let old = GreetRequestV1 { name: "Bob".to_string() };
+        let new: GreetRequestV2 = check_compatible(&old).unwrap();
+        assert_eq!(new.name, "Bob");
+        assert_eq!(new.greeting, None);
+
+        // Older sides skip the fields they do not know.
+        let new = GreetRequestV2 {
+            name: "Bob".to_string(),
+            greeting: Some("Hi".to_string()),
+            tags: BTreeMap::from([("tenant".to_string(), "acme".to_string())]),
+        };
+        assert_eq!(check_compatible::<GreetRequestV2, GreetRequestV1>(&new).unwrap(), old);
+
+        let request: GreetRequest = check_compatible(&GreetRequest::V1(old.clone())).unwrap();
+        assert_eq!(request.into_latest().name, "Bob");
+
+        // An alias only works in one direction.
+        #[derive(Debug, PartialEq, Deserialize, Serialize)]
+        struct Renamed {
+            #[serde(alias = "name")]
+            first_name: String,
+        }
+        let renamed: Renamed = check_compatible(&old).unwrap();
+        assert_eq!(renamed.first_name, "Bob");
+        assert!(check_compatible::<Renamed, GreetRequestV1>(&renamed).is_err());
+
+        // A field whose type changed is not compatible.
+        #[derive(Debug, Deserialize)]
+        struct Retyped {
+            #[allow(dead_code)]
+            name: u32,
+        }
+        assert!(check_compatible::<GreetRequestV1, Retyped>(&old).is_err());
+    }
+
+    #[test]
+    fn check_fixture_test() {
+        let path = std::env::temp_dir().join(crate::transfer::temporary_file_name(Path::new("fixture")));
+
+        let old = GreetRequestV1 { name: "Bob".to_string() };
+        assert_eq!(check_fixture(&path, &old).unwrap(), vec![old; Encoding::ALL.iter().filter(|e| e.is_self_describing()).count()]);
+
+        // The recorded payloads of the old type are decoded with the new type.
+        let current = GreetRequestV2 {
+            name: "Alice".to_string(),
+            greeting: None,
+            tags: BTreeMap::new(),
+        };
+        for decoded in check_fixture(&path, &current).unwrap() {
+            assert_eq!(decoded.name, "Bob");
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
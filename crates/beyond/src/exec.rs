@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+
+use crate::Error;
+
+/// Options for the server process, like its environment and working directory.
+///
+/// They are set on the client with `Client::set_exec_options`, and can be
+/// overridden for single calls with `Client::with_exec_options`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExecOptions {
+    env: Vec<(String, String)>,
+    current_dir: Option<PathBuf>,
+    umask: Option<u32>,
+    nice: Option<i32>,
+}
+
+impl ExecOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set an environment variable. Setting the same variable again replaces its value.
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let name = name.into();
+        self.env.retain(|(existing, _)| *existing != name);
+        self.env.push((name, value.into()));
+        self
+    }
+
+    /// Run the server process in `dir` instead of the home directory.
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Set the umask of the server process, e.g. `0o027`.
+    pub fn umask(mut self, umask: u32) -> Self {
+        self.umask = Some(umask & 0o777);
+        self
+    }
+
+    /// Run the server process with the given niceness, from -20 to 19.
+    pub fn nice(mut self, nice: i32) -> Self {
+        self.nice = Some(nice.clamp(-20, 19));
+        self
+    }
+
+    /// Combine these options with `overrides`, whose values take precedence.
+    pub fn merge(&self, overrides: &ExecOptions) -> Self {
+        let mut merged = self.clone();
+        for (name, value) in &overrides.env {
+            merged = merged.env(name, value);
+        }
+        merged.current_dir = overrides.current_dir.clone().or(merged.current_dir);
+        merged.umask = overrides.umask.or(merged.umask);
+        merged.nice = overrides.nice.or(merged.nice);
+        merged
+    }
+
+    /// Build a shell command that runs `command` with these options.
+    ///
+    /// All values are quoted, so they cannot inject other commands.
+    pub fn command(&self, command: &str) -> Result<String, Error> {
+        self.command_as(command, None)
+    }
+
+    /// Like [`ExecOptions::command`], but runs `command` as `user` with `sudo -n`.
+    #[doc(hidden)]
+    pub fn command_as(&self, command: &str, user: Option<&str>) -> Result<String, Error> {
+        let mut env_and_command = String::new();
+        for (name, value) in &self.env {
+            if !is_valid_env_name(name) {
+                return Err(Error::InvalidEnvironmentVariable { name: name.clone() });
+            }
+            env_and_command.push_str(&format!("{}={} ", name, shell_quote(value)));
+        }
+        if let Some(nice) = self.nice {
+            env_and_command.push_str(&format!("nice -n {} ", nice));
+        }
+        env_and_command.push_str(command);
+
+        let mut wrapped = String::new();
+        if let Some(current_dir) = &self.current_dir {
+            wrapped.push_str(&format!("cd {} && ", shell_quote(&current_dir.to_string_lossy())));
+        }
+        let umask = self.umask.map(|umask| format!("umask {:04o} && ", umask));
+
+        // sudo resets the environment, so the variables are set by `env` after it.
+        // `-n` makes sudo fail instead of prompting for a password. The umask is
+        // set by a shell that runs as `user`, since sudo can override it.
+        match (user, umask) {
+            (Some(user), Some(umask)) => {
+                let elevated = format!("{}exec env {}", umask, env_and_command);
+                wrapped.push_str(&format!("sudo -n -u {} -- sh -c {}", shell_quote(user), shell_quote(&elevated)));
+            }
+            (Some(user), None) => wrapped.push_str(&format!("sudo -n -u {} -- env {}", shell_quote(user), env_and_command)),
+            (None, umask) => {
+                wrapped.push_str(umask.as_deref().unwrap_or_default());
+                wrapped.push_str(&env_and_command);
+            }
+        }
+
+        Ok(wrapped)
+    }
+}
+
+/// Quote `value` for a POSIX shell.
+pub(crate) fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn is_valid_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_options_command_test() {
+        let options = ExecOptions::new()
+            .current_dir("/srv/my app")
+            .umask(0o027)
+            .env("GREETING", "it's me; rm -rf /")
+            .nice(5);
+
+        assert_eq!(
+            options.command("server beyond-server-process hello").unwrap(),
+            r"cd '/srv/my app' && umask 0027 && GREETING='it'\''s me; rm -rf /' nice -n 5 server beyond-server-process hello"
+        );
+
+        let options = options.merge(&ExecOptions::new().env("GREETING", "hi").umask(0o022));
+        assert_eq!(
+            options.command("server").unwrap(),
+            "cd '/srv/my app' && umask 0022 && GREETING='hi' nice -n 5 server"
+        );
+
+        assert_eq!(
+            ExecOptions::new().env("HOME", "/root").command_as("server", Some("root")).unwrap(),
+            "sudo -n -u 'root' -- env HOME='/root' server"
+        );
+        assert_eq!(
+            ExecOptions::new()
+                .current_dir("/srv")
+                .umask(0o027)
+                .env("GREETING", "hi there")
+                .command_as("server", Some("deploy"))
+                .unwrap(),
+            r"cd '/srv' && sudo -n -u 'deploy' -- sh -c 'umask 0027 && exec env GREETING='\''hi there'\'' server'"
+        );
+
+        let options = ExecOptions::new().env("NOT=VALID", "");
+        assert!(matches!(options.command("server"), Err(Error::InvalidEnvironmentVariable { .. })));
+    }
+}
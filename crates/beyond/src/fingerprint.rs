@@ -0,0 +1,434 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{
+    Deserialize,
+    de::{self, DeserializeSeed, IntoDeserializer, Visitor},
+};
+
+/// A structural fingerprint of a type that changes whenever its encoding
+/// changes, e.g. when a field is added, renamed or changes its type.
+pub fn fingerprint<T: for<'de> Deserialize<'de>>() -> u64 {
+    // FNV-1a, which is stable between builds, unlike `DefaultHasher`.
+    schema::<T>().bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3))
+}
+
+/// Describe the structure of a type, as seen by its `Deserialize` implementation.
+///
+/// The type is "deserialized" from a tracer that records what is asked for.
+/// Since only one variant of an enum can be deserialized at a time, this is
+/// repeated until every variant of every enum was seen.
+///
+/// Types that only accept specific values, like some strings, are described up
+/// to the point where they reject the placeholder value. Recursive types are
+/// described up to the first recursion.
+pub fn schema<T: for<'de> Deserialize<'de>>() -> String {
+    // Stops types with an enormous number of enum variant combinations.
+    const MAX_RUNS: usize = 256;
+
+    let mut enums = EnumChoices::default();
+    let mut runs = BTreeSet::new();
+
+    for _ in 0..MAX_RUNS {
+        let mut output = String::new();
+        let result = T::deserialize(Tracer {
+            output: &mut output,
+            enums: &mut enums,
+            types: &mut vec![],
+        });
+        if let Err(e) = result {
+            output.push_str(&format!("!{}", e.0));
+        }
+        runs.insert(output);
+
+        if !enums.advance() {
+            break;
+        }
+    }
+
+    runs.into_iter().collect::<Vec<_>>().join("\n")
+}
+
+/// Which variant is picked for each enum in the current run.
+#[derive(Default)]
+struct EnumChoices {
+    // The picked variant and the number of variants, by enum name.
+    choices: HashMap<&'static str, (usize, usize)>,
+}
+
+impl EnumChoices {
+    fn choose(&mut self, name: &'static str, variant_count: usize) -> usize {
+        self.choices.entry(name).or_insert((0, variant_count)).0
+    }
+
+    /// Pick the next variant of an enum that still has unseen variants.
+    fn advance(&mut self) -> bool {
+        let mut names: Vec<_> = self.choices.keys().copied().collect();
+        names.sort();
+
+        for name in names {
+            let (choice, variant_count) = self.choices.get_mut(name).expect("the name was just taken from the map");
+            if *choice + 1 < *variant_count {
+                *choice += 1;
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+#[derive(Debug)]
+struct TraceError(String);
+
+impl std::fmt::Display for TraceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl de::Error for TraceError {
+    fn custom<M: std::fmt::Display>(message: M) -> Self {
+        Self(message.to_string())
+    }
+}
+
+/// Marks a type that contains itself, which cannot be traced any further.
+const RECURSION: &str = "recursion";
+
+struct Tracer<'a> {
+    output: &'a mut String,
+    enums: &'a mut EnumChoices,
+    // The named types that are currently being traced, to detect recursion.
+    types: &'a mut Vec<&'static str>,
+}
+
+impl Tracer<'_> {
+    fn reborrow(&mut self) -> Tracer<'_> {
+        Tracer {
+            output: self.output,
+            enums: self.enums,
+            types: self.types,
+        }
+    }
+
+    /// Trace a struct, enum or newtype with `trace`, or write a reference back to it if it contains itself.
+    fn trace_named<T>(mut self, name: &'static str, trace: impl FnOnce(Tracer<'_>) -> Result<T, TraceError>) -> Result<T, TraceError> {
+        if self.types.contains(&name) {
+            self.output.push_str(&format!("{}({}) ", RECURSION, name));
+            return Err(TraceError(RECURSION.to_string()));
+        }
+
+        self.types.push(name);
+        let value = trace(self.reborrow());
+        self.types.pop();
+        value
+    }
+
+    /// Trace the fields of a struct or struct variant.
+    fn trace_fields<'de, V: Visitor<'de>>(mut self, fields: &'static [&'static str], visitor: V) -> Result<V::Value, TraceError> {
+        self.output.push_str("{ ");
+        let value = visitor.visit_map(MapTracer {
+            tracer: self.reborrow(),
+            keys: Some(fields),
+            remaining: fields.len(),
+        });
+        self.output.push_str("} ");
+        value
+    }
+}
+
+macro_rules! trace_primitive {
+    ($($method:ident $visit:ident $value:expr;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+                self.output.push_str(stringify!($value));
+                self.output.push(' ');
+                visitor.$visit($value)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Tracer<'_> {
+    type Error = TraceError;
+
+    trace_primitive! {
+        deserialize_bool visit_bool false;
+        deserialize_i8 visit_i8 0i8;
+        deserialize_i16 visit_i16 0i16;
+        deserialize_i32 visit_i32 0i32;
+        deserialize_i64 visit_i64 0i64;
+        deserialize_i128 visit_i128 0i128;
+        deserialize_u8 visit_u8 0u8;
+        deserialize_u16 visit_u16 0u16;
+        deserialize_u32 visit_u32 0u32;
+        deserialize_u64 visit_u64 0u64;
+        deserialize_u128 visit_u128 0u128;
+        deserialize_f32 visit_f32 0f32;
+        deserialize_f64 visit_f64 0f64;
+        deserialize_char visit_char 'c';
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.output.push_str("str ");
+        visitor.visit_str("")
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.output.push_str("bytes ");
+        visitor.visit_bytes(&[])
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.output.push_str("option ");
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.output.push_str("unit ");
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value, TraceError> {
+        // Newtypes are encoded like the type they wrap.
+        self.trace_named(name, |tracer| visitor.visit_newtype_struct(tracer))
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.output.push_str("seq ");
+        visitor.visit_seq(SeqTracer {
+            tracer: self,
+            remaining: 1,
+            fixed_length: false,
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, TraceError> {
+        self.output.push_str(&format!("tuple{} ", len));
+        visitor.visit_seq(SeqTracer {
+            tracer: self,
+            remaining: len,
+            fixed_length: true,
+        })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, len: usize, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.output.push_str("map ");
+        visitor.visit_map(MapTracer {
+            tracer: self,
+            keys: None,
+            remaining: 1,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, name: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value, TraceError> {
+        self.trace_named(name, |tracer| tracer.trace_fields(fields, visitor))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value, TraceError> {
+        // Every nested value of the same enum would pick the same variant, so recursion has to stop here.
+        self.trace_named(name, |tracer| {
+            let choice = tracer.enums.choose(name, variants.len());
+            tracer.output.push_str(&format!("enum[{}] {} ", variants.join(","), variants.get(choice).copied().unwrap_or_default()));
+            visitor.visit_enum(EnumTracer {
+                tracer,
+                variant: variants.get(choice).copied().unwrap_or_default(),
+            })
+        })
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        // There is no way to know what a self-describing type expects.
+        self.output.push_str("any ");
+        visitor.visit_unit()
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        visitor.visit_unit()
+    }
+}
+
+struct SeqTracer<'a> {
+    tracer: Tracer<'a>,
+    remaining: usize,
+    // Whether it is a tuple, which cannot end early.
+    fixed_length: bool,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqTracer<'_> {
+    type Error = TraceError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, TraceError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+
+        match seed.deserialize(self.tracer.reborrow()) {
+            Ok(value) => Ok(Some(value)),
+            // An empty sequence ends the recursion.
+            Err(e) if e.0 == RECURSION && !self.fixed_length => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+struct MapTracer<'a> {
+    tracer: Tracer<'a>,
+    // The field names of a struct, or `None` for a map with one entry.
+    keys: Option<&'static [&'static str]>,
+    remaining: usize,
+}
+
+impl<'de> de::MapAccess<'de> for MapTracer<'_> {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, TraceError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+
+        match self.keys {
+            Some(keys) => {
+                let key = keys[keys.len() - self.remaining - 1];
+                self.tracer.output.push_str(&format!("{}: ", key));
+                seed.deserialize(key.into_deserializer()).map(Some)
+            }
+            None => seed.deserialize(self.tracer.reborrow()).map(Some),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, TraceError> {
+        seed.deserialize(self.tracer.reborrow())
+    }
+}
+
+struct EnumTracer<'a> {
+    tracer: Tracer<'a>,
+    variant: &'static str,
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for EnumTracer<'a> {
+    type Error = TraceError;
+    type Variant = Tracer<'a>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Tracer<'a>), TraceError> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, self.tracer))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Tracer<'_> {
+    type Error = TraceError;
+
+    fn unit_variant(self) -> Result<(), TraceError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, TraceError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, TraceError> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value, TraceError> {
+        self.trace_fields(fields, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Request {
+        name: String,
+        tags: Vec<String>,
+        kind: Kind,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Kind {
+        Plain,
+        Sized(u32),
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct RenamedRequest {
+        #[serde(rename = "title")]
+        name: String,
+        tags: Vec<String>,
+        kind: Kind,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Tree {
+        children: Vec<Tree>,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Expr {
+        Lit(u32),
+        Add(Box<Expr>, Box<Expr>),
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct List(Option<Box<List>>);
+
+    #[test]
+    fn schema_test() {
+        assert_eq!(
+            schema::<Request>(),
+            "{ name: str tags: seq str kind: enum[Plain,Sized] Plain } \n{ name: str tags: seq str kind: enum[Plain,Sized] Sized 0u32 } "
+        );
+        assert_eq!(schema::<Tree>(), "{ children: seq recursion(Tree) } ");
+    }
+
+    #[test]
+    fn recursive_schema_test() {
+        assert_eq!(
+            schema::<Expr>(),
+            "enum[Lit,Add] Add tuple2 recursion(Expr) !recursion\nenum[Lit,Add] Lit 0u32 "
+        );
+        assert_eq!(schema::<List>(), "option recursion(List) !recursion");
+        assert_ne!(fingerprint::<Expr>(), fingerprint::<List>());
+    }
+
+    #[test]
+    fn fingerprint_test() {
+        assert_eq!(fingerprint::<Request>(), fingerprint::<Request>());
+        assert_ne!(fingerprint::<Request>(), fingerprint::<RenamedRequest>());
+        assert_ne!(fingerprint::<u32>(), fingerprint::<u64>());
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::{Condvar, Mutex},
+};
+
+use crate::Error;
+
+/// Clients for many hosts that routes can be called on in parallel.
+///
+/// `C` is usually the `Client` generated by [`Beyond`](crate::Beyond):
+///
+/// ```ignore
+/// let mut fleet = beyond::Fleet::new(10);
+/// let connected = fleet.connect(destinations, |destination| Client::new(destination, "server".to_string()));
+/// let results = fleet.call(|client| client.hello(HelloRequest { name: "Bob".to_string() }));
+/// println!("{}", results.summary());
+/// ```
+pub struct Fleet<C> {
+    clients: Vec<(String, C)>,
+    concurrency: usize,
+}
+
+impl<C> Fleet<C> {
+    /// Create an empty fleet that talks to at most `concurrency` hosts at the same time.
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            clients: vec![],
+            concurrency: concurrency.max(1),
+        }
+    }
+
+    /// Add the client for a host that is already connected.
+    ///
+    /// Results are reported by destination, so every destination is in the fleet
+    /// only once. The client of a destination that was already added is replaced.
+    pub fn add(&mut self, destination: impl Into<String>, client: C) {
+        let destination = destination.into();
+        match self.clients.iter_mut().find(|(existing, _)| *existing == destination) {
+            Some((_, existing_client)) => *existing_client = client,
+            None => self.clients.push((destination, client)),
+        }
+    }
+
+    /// The destinations of all hosts in the fleet.
+    pub fn destinations(&self) -> impl Iterator<Item = &str> {
+        self.clients.iter().map(|(destination, _)| destination.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+impl<C: Send> Fleet<C> {
+    /// Connect to all `destinations` in parallel and add the hosts that
+    /// connected successfully to the fleet.
+    ///
+    /// Every destination is connected to once, even if it is given multiple times.
+    /// Hosts that are already in the fleet are connected to again and replaced.
+    pub fn connect<D, F>(&mut self, destinations: impl IntoIterator<Item = D>, connect: F) -> FleetResults<()>
+    where
+        D: Into<String>,
+        F: Fn(&str) -> Result<C, Error> + Sync,
+    {
+        let mut seen = BTreeSet::new();
+        let destinations: Vec<String> = destinations.into_iter().map(Into::into).filter(|destination| seen.insert(destination.clone())).collect();
+        let connections = run_parallel(destinations, self.concurrency, |destination| {
+            let client = connect(&destination);
+            (destination, client)
+        });
+
+        let mut results = BTreeMap::new();
+        for (destination, client) in connections {
+            match client {
+                Ok(client) => {
+                    self.add(destination.clone(), client);
+                    results.insert(destination, Ok(()));
+                }
+                Err(e) => {
+                    results.insert(destination, Err(e));
+                }
+            }
+        }
+
+        FleetResults { results }
+    }
+
+    /// Call `f` with the client of every host in parallel, e.g. to call the same route on all of them.
+    pub fn call<R, F>(&mut self, f: F) -> FleetResults<R>
+    where
+        R: Send,
+        F: Fn(&mut C) -> Result<R, Error> + Sync,
+    {
+        let results = run_parallel(self.clients.iter_mut(), self.concurrency, |(destination, client)| (destination.clone(), f(client)));
+
+        FleetResults {
+            results: results.into_iter().collect(),
+        }
+    }
+
+    /// Distribute `inputs` across the hosts and call `f` with a client and one input at a time.
+    ///
+    /// Every host works on one input at a time, and idle hosts pick up the next
+    /// input, so faster hosts process more of them. If `f` fails, the input is
+    /// retried on a host that has not tried it yet, until every host failed on it.
+    ///
+    /// The results are returned in the order of the inputs.
+    pub fn map<T, R, F>(&mut self, inputs: impl IntoIterator<Item = T>, f: F) -> Vec<Result<R, Error>>
+    where
+        T: Clone + Send,
+        R: Send,
+        F: Fn(&mut C, T) -> Result<R, Error> + Sync,
+    {
+        let inputs: Vec<T> = inputs.into_iter().collect();
+        if self.clients.is_empty() {
+            return inputs.iter().map(|_| Err(Error::EmptyFleet)).collect();
+        }
+
+        let host_count = self.clients.len();
+        let input_count = inputs.len();
+        let state = Mutex::new(MapState {
+            pending: inputs
+                .into_iter()
+                .enumerate()
+                .map(|(index, input)| PendingInput {
+                    index,
+                    input,
+                    tried_hosts: vec![],
+                })
+                .collect(),
+            idle_hosts: self.clients.iter_mut().map(|(_, client)| Some(client)).collect(),
+            in_flight: 0,
+            results: std::iter::repeat_with(|| None).take(input_count).collect(),
+        });
+        let changed = Condvar::new();
+
+        std::thread::scope(|scope| {
+            for _ in 0..self.concurrency.min(host_count) {
+                scope.spawn(|| {
+                    let mut guard = state.lock().unwrap();
+                    loop {
+                        let Some((mut pending, host, client)) = guard.next_assignment() else {
+                            if guard.pending.is_empty() && guard.in_flight == 0 {
+                                break;
+                            }
+                            guard = changed.wait(guard).unwrap();
+                            continue;
+                        };
+
+                        // The lock is released while the host works on the input.
+                        guard.in_flight += 1;
+                        drop(guard);
+                        let result = f(client, pending.input.clone());
+                        guard = state.lock().unwrap();
+                        guard.in_flight -= 1;
+                        guard.idle_hosts[host] = Some(client);
+
+                        match result {
+                            Err(_) if pending.tried_hosts.len() + 1 < host_count => {
+                                pending.tried_hosts.push(host);
+                                guard.pending.push_front(pending);
+                            }
+                            result => guard.results[pending.index] = Some(result),
+                        }
+                        changed.notify_all();
+                    }
+                });
+            }
+        });
+
+        state
+            .into_inner()
+            .unwrap()
+            .results
+            .into_iter()
+            .map(|result| result.expect("every input is processed"))
+            .collect()
+    }
+}
+
+/// The shared state of the threads of [`Fleet::map`].
+struct MapState<'a, C, T, R> {
+    pending: VecDeque<PendingInput<T>>,
+    // The clients of the hosts that are not working on an input, by host index.
+    idle_hosts: Vec<Option<&'a mut C>>,
+    in_flight: usize,
+    results: Vec<Option<Result<R, Error>>>,
+}
+
+struct PendingInput<T> {
+    index: usize,
+    input: T,
+    // The hosts that already failed on this input.
+    tried_hosts: Vec<usize>,
+}
+
+impl<'a, C, T, R> MapState<'a, C, T, R> {
+    /// Take the first pending input that an idle host has not tried yet, together with that host.
+    fn next_assignment(&mut self) -> Option<(PendingInput<T>, usize, &'a mut C)> {
+        for position in 0..self.pending.len() {
+            let tried_hosts = &self.pending[position].tried_hosts;
+            let Some(host) = (0..self.idle_hosts.len()).find(|host| self.idle_hosts[*host].is_some() && !tried_hosts.contains(host)) else {
+                continue;
+            };
+
+            let pending = self.pending.remove(position)?;
+            let client = self.idle_hosts[host].take()?;
+            return Some((pending, host, client));
+        }
+
+        None
+    }
+}
+
+/// Call `f` on every item using at most `concurrency` threads.
+///
+/// The results are returned in the order of the items.
+pub(crate) fn run_parallel<T, R, F>(items: impl IntoIterator<Item = T>, concurrency: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let queue = Mutex::new(items.into_iter().enumerate().collect::<Vec<_>>().into_iter());
+    let results = Mutex::new(Vec::new());
+
+    std::thread::scope(|scope| {
+        for _ in 0..concurrency.max(1) {
+            scope.spawn(|| {
+                loop {
+                    // The lock is released before `f` is called, so that the other threads can continue.
+                    let next = queue.lock().unwrap().next();
+                    let Some((index, item)) = next else {
+                        break;
+                    };
+
+                    let result = f(item);
+                    results.lock().unwrap().push((index, result));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// The result of an operation on every host of a [`Fleet`], by destination.
+#[derive(Debug)]
+pub struct FleetResults<R> {
+    results: BTreeMap<String, Result<R, Error>>,
+}
+
+impl<R> FleetResults<R> {
+    /// The result for a single host.
+    pub fn get(&self, destination: &str) -> Option<&Result<R, Error>> {
+        self.results.get(destination)
+    }
+
+    /// The hosts the operation succeeded on.
+    pub fn successes(&self) -> impl Iterator<Item = (&str, &R)> {
+        self.results
+            .iter()
+            .filter_map(|(destination, result)| result.as_ref().ok().map(|value| (destination.as_str(), value)))
+    }
+
+    /// The hosts the operation failed on.
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &Error)> {
+        self.results
+            .iter()
+            .filter_map(|(destination, result)| result.as_ref().err().map(|e| (destination.as_str(), e)))
+    }
+
+    /// Count the successes and collect the failures.
+    pub fn summary(&self) -> FleetSummary {
+        FleetSummary {
+            succeeded: self.successes().count(),
+            failed: self
+                .failures()
+                .map(|(destination, e)| (destination.to_string(), e.to_string()))
+                .collect(),
+        }
+    }
+
+    pub fn into_inner(self) -> BTreeMap<String, Result<R, Error>> {
+        self.results
+    }
+}
+
+impl<R> IntoIterator for FleetResults<R> {
+    type Item = (String, Result<R, Error>);
+    type IntoIter = std::collections::btree_map::IntoIter<String, Result<R, Error>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.results.into_iter()
+    }
+}
+
+/// An overview of the results of an operation on a [`Fleet`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FleetSummary {
+    pub succeeded: usize,
+    /// The destinations of the hosts that failed and their errors.
+    pub failed: Vec<(String, String)>,
+}
+
+impl std::fmt::Display for FleetSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} succeeded, {} failed", self.succeeded, self.failed.len())?;
+        for (destination, e) in &self.failed {
+            write!(f, "\n  {}: {}", destination, e)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fleet_call_test() {
+        let mut fleet = Fleet::new(2);
+        let connected = fleet.connect(["a", "b", "c"], |destination| match destination {
+            "b" => Err(Error::MissingResponse),
+            _ => Ok(destination.to_uppercase()),
+        });
+        assert_eq!(connected.summary().succeeded, 2);
+        assert_eq!(fleet.destinations().collect::<Vec<_>>(), vec!["a", "c"]);
+
+        let results = fleet.call(|client| match client.as_str() {
+            "A" => Ok(1),
+            _ => Err(Error::MissingResponse),
+        });
+        assert_eq!(results.successes().collect::<Vec<_>>(), vec![("a", &1)]);
+        assert_eq!(
+            results.summary(),
+            FleetSummary {
+                succeeded: 1,
+                failed: vec![("c".to_string(), Error::MissingResponse.to_string())],
+            }
+        );
+    }
+
+    #[test]
+    fn fleet_duplicate_test() {
+        let mut fleet = Fleet::new(2);
+        let connected = fleet.connect(["a", "b", "a"], |destination| Ok(destination.to_uppercase()));
+        assert_eq!(connected.summary().succeeded, 2);
+        assert_eq!(fleet.destinations().collect::<Vec<_>>(), vec!["a", "b"]);
+
+        // Adding a destination again replaces its client, so no result is lost.
+        fleet.add("b", "B2".to_string());
+        assert_eq!(fleet.len(), 2);
+
+        let results = fleet.call(|client| Ok(client.clone()));
+        assert_eq!(results.successes().collect::<Vec<_>>(), vec![("a", &"A".to_string()), ("b", &"B2".to_string())]);
+    }
+
+    #[test]
+    fn fleet_map_test() {
+        let mut fleet = Fleet::new(4);
+        for host in ["a", "b", "c"] {
+            fleet.add(host, host);
+        }
+
+        // Host "b" fails on every input, so its inputs are retried on the other hosts.
+        let results = fleet.map(0..50, |client, input| match *client {
+            "b" => Err(Error::MissingResponse),
+            _ => Ok(input * 2),
+        });
+        assert_eq!(results.into_iter().map(Result::unwrap).collect::<Vec<_>>(), (0..50).map(|n| n * 2).collect::<Vec<_>>());
+
+        // An input that fails on every host returns the last error.
+        let results = fleet.map([1, 2], |_, input| match input {
+            1 => Err(Error::MissingResponse),
+            _ => Ok(input),
+        });
+        assert!(matches!(results[0], Err(Error::MissingResponse)));
+        assert!(matches!(results[1], Ok(2)));
+
+        let results = Fleet::<()>::new(1).map([1], |_, input| Ok(input));
+        assert!(matches!(results[0], Err(Error::EmptyFleet)));
+    }
+
+    #[test]
+    fn run_parallel_test() {
+        let results = run_parallel(0..100, 8, |n| n * 2);
+        assert_eq!(results, (0..100).map(|n| n * 2).collect::<Vec<_>>());
+    }
+}
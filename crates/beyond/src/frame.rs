@@ -0,0 +1,232 @@
+use std::io::{BufRead, Read, Write};
+
+/// The environment variable that tells the server process to use binary frames.
+pub const FRAMING_ENV: &str = "BEYOND_FRAMING";
+
+/// Starts every frame. It is never the first byte of a UTF-8 character, so it
+/// never starts a line of text, and both kinds of messages can be told apart.
+pub const FRAME_MAGIC: u8 = 0xBE;
+
+/// How requests and responses are sent between the client and the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// One line of base64 per payload. Requests with a single request are
+    /// passed as a command-line argument.
+    #[default]
+    Text,
+    /// Length-prefixed binary frames with a checksum, without base64.
+    /// All requests are written to stdin.
+    Binary,
+}
+
+impl Framing {
+    /// All framings.
+    pub const ALL: &'static [Framing] = &[Framing::Text, Framing::Binary];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Framing::Text => "text",
+            Framing::Binary => "binary",
+        }
+    }
+
+    /// The framing the client asked the server process to use.
+    pub fn from_env() -> Self {
+        match std::env::var(FRAMING_ENV).as_deref() {
+            Ok("binary") => Framing::Binary,
+            _ => Framing::Text,
+        }
+    }
+}
+
+/// What the body of a frame contains.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    /// A request or response.
+    Payload,
+    /// A log record, see [`LOG_PREFIX`](crate::logging::LOG_PREFIX).
+    Log,
+    /// A progress update, see [`PROGRESS_PREFIX`](crate::progress::PROGRESS_PREFIX).
+    Progress,
+    /// The path of a file the response was spilled to, see [`SPILL_PREFIX`](crate::serde::SPILL_PREFIX).
+    Spill,
+}
+
+impl FrameKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            FrameKind::Payload => 0,
+            FrameKind::Log => 1,
+            FrameKind::Progress => 2,
+            FrameKind::Spill => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(FrameKind::Payload),
+            1 => Some(FrameKind::Log),
+            2 => Some(FrameKind::Progress),
+            3 => Some(FrameKind::Spill),
+            _ => None,
+        }
+    }
+}
+
+/// A binary message between the client and the server.
+///
+/// On the wire, it is the magic byte, the kind, the length of the marker as one byte,
+/// the marker, the length of the body as four big-endian bytes, the body, and the
+/// CRC-32 of everything after the magic byte as four big-endian bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// The codec and compression of the body, like the marker of a text payload.
+    pub marker: String,
+    pub body: Vec<u8>,
+}
+
+/// A message that was read from stdin or stdout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Line(String),
+    Frame(Frame),
+}
+
+/// Write a frame in a single call, so that a log record or progress update
+/// written to stdout by another thread cannot end up in the middle of it.
+pub fn write_frame(output: &mut impl Write, frame: &Frame) -> std::io::Result<()> {
+    output.write_all(&encode_frame(frame)?)
+}
+
+/// Get the bytes of a frame on the wire.
+pub(crate) fn encode_frame(frame: &Frame) -> std::io::Result<Vec<u8>> {
+    let marker_len = u8::try_from(frame.marker.len()).map_err(|_| invalid_data("the marker of a frame is too long"))?;
+    let body_len = u32::try_from(frame.body.len()).map_err(|_| invalid_data("the body of a frame is too long"))?;
+
+    let mut bytes = Vec::with_capacity(1 + 2 + frame.marker.len() + 4 + frame.body.len() + 4);
+    bytes.push(FRAME_MAGIC);
+    bytes.extend_from_slice(&[frame.kind.to_byte(), marker_len]);
+    bytes.extend_from_slice(frame.marker.as_bytes());
+    bytes.extend_from_slice(&body_len.to_be_bytes());
+    bytes.extend_from_slice(&frame.body);
+
+    // The checksum covers everything after the magic byte.
+    let checksum = crc32fast::hash(&bytes[1..]);
+    bytes.extend_from_slice(&checksum.to_be_bytes());
+    Ok(bytes)
+}
+
+/// Read the next line or frame, or `None` at the end of the input.
+///
+/// A frame whose checksum does not match is an error of kind `InvalidData`.
+pub fn read_message(input: &mut impl BufRead) -> std::io::Result<Option<Message>> {
+    let first_byte = match input.fill_buf()?.first() {
+        Some(byte) => *byte,
+        None => return Ok(None),
+    };
+
+    if first_byte != FRAME_MAGIC {
+        let mut line = String::new();
+        input.read_line(&mut line)?;
+        return Ok(Some(Message::Line(line)));
+    }
+    input.consume(1);
+
+    let mut hasher = crc32fast::Hasher::new();
+
+    let mut kind_and_marker_len = [0; 2];
+    input.read_exact(&mut kind_and_marker_len)?;
+    hasher.update(&kind_and_marker_len);
+    let [kind, marker_len] = kind_and_marker_len;
+
+    let mut marker = vec![0; marker_len as usize];
+    input.read_exact(&mut marker)?;
+    hasher.update(&marker);
+
+    let mut body_len = [0; 4];
+    input.read_exact(&mut body_len)?;
+    hasher.update(&body_len);
+
+    // The body is not allocated up front, since a corrupted length could be huge.
+    let body_len = u32::from_be_bytes(body_len) as u64;
+    let mut body = vec![];
+    input.by_ref().take(body_len).read_to_end(&mut body)?;
+    if body.len() as u64 != body_len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    hasher.update(&body);
+
+    let mut checksum = [0; 4];
+    input.read_exact(&mut checksum)?;
+    if hasher.finalize() != u32::from_be_bytes(checksum) {
+        return Err(invalid_data("the checksum of a frame does not match, it was corrupted"));
+    }
+
+    Ok(Some(Message::Frame(Frame {
+        kind: FrameKind::from_byte(kind).ok_or_else(|| invalid_data("a frame has an unknown kind"))?,
+        marker: String::from_utf8(marker).map_err(|_| invalid_data("the marker of a frame is not UTF-8"))?,
+        body,
+    })))
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_test() {
+        let frame = Frame {
+            kind: FrameKind::Payload,
+            marker: "msgpack+zstd".to_string(),
+            body: vec![0, FRAME_MAGIC, b'\n', 255],
+        };
+
+        let mut output = b"@log line\n".to_vec();
+        write_frame(&mut output, &frame).unwrap();
+        let mut input = output.as_slice();
+
+        assert_eq!(read_message(&mut input).unwrap(), Some(Message::Line("@log line\n".to_string())));
+        assert_eq!(read_message(&mut input).unwrap(), Some(Message::Frame(frame.clone())));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+
+        // Flipping a single bit of the body is detected.
+        let mut output = vec![];
+        write_frame(&mut output, &frame).unwrap();
+        let body_start = output.len() - 4 - frame.body.len();
+        output[body_start] ^= 1;
+        let error = read_message(&mut output.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn write_frame_once_test() {
+        /// Counts the calls, since stdout is only locked for the duration of one call.
+        struct Writes(Vec<Vec<u8>>);
+
+        impl Write for Writes {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.push(buf.to_vec());
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let frame = Frame {
+            kind: FrameKind::Payload,
+            marker: "json".to_string(),
+            body: b"{}".to_vec(),
+        };
+        let mut output = Writes(vec![]);
+        write_frame(&mut output, &frame).unwrap();
+        assert_eq!(output.0.len(), 1);
+        assert_eq!(read_message(&mut output.0[0].as_slice()).unwrap(), Some(Message::Frame(frame)));
+    }
+}
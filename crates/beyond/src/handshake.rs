@@ -0,0 +1,342 @@
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Compression, Encoding, Error, Framing,
+    client::{Uploads, Wire},
+    ssh::SSH,
+};
+
+/// The version of the protocol between the client and the server.
+///
+/// It changes whenever a client could not talk to an older server or the
+/// other way around, e.g. because requests are encoded differently.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The argument in place of a route that makes the server answer with a [`ServerHello`].
+pub const HELLO: &str = "--hello";
+
+/// The versions a server binary reports in the handshake.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ServerHello {
+    /// The version of the protocol between the client and the server.
+    pub protocol_version: u32,
+    /// The version of `beyond` the server binary was built with.
+    pub beyond_version: String,
+    /// The version of the crate the server binary was built from.
+    pub app_version: String,
+    /// The fingerprints of the routes the server binary supports.
+    #[serde(default)]
+    pub routes: Vec<RouteFingerprint>,
+    /// The temporary directory of the SSH user on the server, where uploaded files go.
+    #[serde(default)]
+    pub temp_dir: Option<String>,
+    /// The names of the encodings the server binary supports. JSON is always supported.
+    #[serde(default)]
+    pub encodings: Vec<String>,
+    /// The names of the compressions the server binary supports.
+    #[serde(default)]
+    pub compressions: Vec<String>,
+    /// The names of the framings the server binary supports. Text framing is always supported.
+    #[serde(default)]
+    pub framings: Vec<String>,
+}
+
+impl ServerHello {
+    /// The wire options the client asked for, with the ones the server binary does not support replaced by the defaults.
+    pub fn negotiate(&self, wire: Wire) -> Wire {
+        let mut negotiated = wire;
+        if !self.encodings.iter().any(|name| name == wire.encoding.name()) {
+            negotiated.encoding = Encoding::Json;
+        }
+        if !self.compressions.iter().any(|name| name == wire.compression.name()) {
+            negotiated.compression = Compression::None;
+        }
+        if !self.framings.iter().any(|name| name == wire.framing.name()) {
+            negotiated.framing = Framing::Text;
+        }
+        negotiated
+    }
+}
+
+/// The fingerprints of the request and response types of a route.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RouteFingerprint {
+    pub name: String,
+    pub request: u64,
+    pub response: u64,
+    /// Whether the types may differ between the client and the server, see [`evolution`](crate::evolution).
+    #[serde(default)]
+    pub evolving: bool,
+}
+
+impl RouteFingerprint {
+    pub fn new<Request: for<'de> Deserialize<'de>, Response: for<'de> Deserialize<'de>>(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            request: crate::fingerprint::fingerprint::<Request>(),
+            response: crate::fingerprint::fingerprint::<Response>(),
+            evolving: false,
+        }
+    }
+}
+
+/// The result of a successful handshake.
+#[derive(Clone, Debug)]
+pub struct Handshake {
+    pub server_hello: ServerHello,
+    /// The routes of the client that the server does not have, or has with different types.
+    pub incompatible_routes: Vec<String>,
+}
+
+/// Answer the handshake on the server.
+pub fn write_hello(output: &mut impl Write, app_version: &str, routes: Vec<RouteFingerprint>) -> Result<(), Error> {
+    crate::serde::write_response(
+        output,
+        ServerHello {
+            protocol_version: PROTOCOL_VERSION,
+            beyond_version: env!("CARGO_PKG_VERSION").to_string(),
+            app_version: app_version.to_string(),
+            routes,
+            temp_dir: Some(std::env::temp_dir().to_string_lossy().into_owned()),
+            encodings: Encoding::ALL.iter().map(|encoding| encoding.name().to_string()).collect(),
+            compressions: Compression::ALL.iter().map(|compression| compression.name().to_string()).collect(),
+            framings: Framing::ALL.iter().map(|framing| framing.name().to_string()).collect(),
+        },
+    )
+}
+
+/// Ask the server for its versions with `command` and check if the client can talk to it.
+///
+/// A different protocol version is an error. A different app version and routes
+/// whose types differ between the client and the server are only warnings,
+/// since the other routes still work. Evolving routes with different types still work too.
+pub fn handshake(ssh: &SSH, command: &str, app_version: &str, routes: &[RouteFingerprint]) -> Result<Handshake, Error> {
+    let process = ssh.execute_streaming(command)?;
+    let (hello, _) = crate::client::receive_response(ssh.clone(), HELLO, None, process, Uploads::new(ssh), &mut |_| {}).map_err(|e| match e {
+        // Servers from before the handshake treat it as an unknown route.
+        Error::SSHProcessExecute { stderr } if stderr.contains(HELLO) => Error::IncompatibleProtocol {
+            client: PROTOCOL_VERSION,
+            server: None,
+        },
+        e => e,
+    })?;
+
+    if !check_hello(&hello, app_version)? {
+        crate::logging::warn(
+            ssh.destination(),
+            &format!("the server binary was built from version {} instead of {}", hello.app_version, app_version),
+        );
+    }
+
+    let incompatible_routes = incompatible_routes(&hello, routes);
+    if !incompatible_routes.is_empty() {
+        crate::logging::warn(
+            ssh.destination(),
+            &format!("the server binary has different types for the routes {}", incompatible_routes.join(", ")),
+        );
+    }
+
+    let evolved_routes = evolved_routes(&hello, routes);
+    if !evolved_routes.is_empty() {
+        crate::logging::warn(
+            ssh.destination(),
+            &format!("the server binary has other versions of the types of the evolving routes {}", evolved_routes.join(", ")),
+        );
+    }
+
+    Ok(Handshake {
+        server_hello: hello,
+        incompatible_routes,
+    })
+}
+
+/// The wire options the client asked for, limited to the ones the server binary supports, see [`ServerHello::negotiate`].
+///
+/// Every option that has to fall back is logged as a warning.
+pub fn negotiate_wire(ssh: &SSH, wire: Wire, hello: &ServerHello) -> Wire {
+    let negotiated = hello.negotiate(wire);
+    if negotiated.encoding != wire.encoding {
+        crate::logging::warn(
+            ssh.destination(),
+            &format!("the server binary does not support the codec {}, so JSON is used instead", wire.encoding.name()),
+        );
+    }
+    if negotiated.compression != wire.compression {
+        crate::logging::warn(
+            ssh.destination(),
+            &format!("the server binary does not support the compression {}, so payloads are not compressed", wire.compression.name()),
+        );
+    }
+    if negotiated.framing != wire.framing {
+        crate::logging::warn(
+            ssh.destination(),
+            &format!("the server binary does not support {} framing, so text framing is used instead", wire.framing.name()),
+        );
+    }
+    negotiated
+}
+
+/// Warn if the client has evolving routes but encodes with a codec that is not self-describing,
+/// since their types cannot differ between the client and the server with it, see [`evolution`](crate::evolution).
+pub fn check_encoding(ssh: &SSH, encoding: Encoding, routes: &[RouteFingerprint]) {
+    let evolving_routes = evolving_routes(encoding, routes);
+    if !evolving_routes.is_empty() {
+        crate::logging::warn(
+            ssh.destination(),
+            &format!("the codec {} cannot evolve types, but the routes {} are evolving", encoding.name(), evolving_routes.join(", ")),
+        );
+    }
+}
+
+/// The evolving routes, if `encoding` is not self-describing.
+fn evolving_routes(encoding: Encoding, routes: &[RouteFingerprint]) -> Vec<String> {
+    if encoding.is_self_describing() {
+        return vec![];
+    }
+    routes.iter().filter(|route| route.evolving).map(|route| route.name.clone()).collect()
+}
+
+/// Fail if a route is one of the incompatible routes of the handshake.
+pub fn check_route(handshake: &Handshake, route: &str) -> Result<(), Error> {
+    if handshake.incompatible_routes.iter().any(|incompatible_route| incompatible_route == route) {
+        return Err(Error::IncompatibleRoute { route: route.to_string() });
+    }
+    Ok(())
+}
+
+/// The routes of the client that the server does not have, or has with different
+/// fingerprints unless they are evolving.
+fn incompatible_routes(hello: &ServerHello, routes: &[RouteFingerprint]) -> Vec<String> {
+    routes
+        .iter()
+        .filter(|route| match server_route(hello, route) {
+            Some(server_route) => !route.evolving && !same_types(route, server_route),
+            None => true,
+        })
+        .map(|route| route.name.clone())
+        .collect()
+}
+
+/// The evolving routes of the client that the server has with different fingerprints.
+fn evolved_routes(hello: &ServerHello, routes: &[RouteFingerprint]) -> Vec<String> {
+    routes
+        .iter()
+        .filter(|route| route.evolving && server_route(hello, route).is_some_and(|server_route| !same_types(route, server_route)))
+        .map(|route| route.name.clone())
+        .collect()
+}
+
+fn server_route<'a>(hello: &'a ServerHello, route: &RouteFingerprint) -> Option<&'a RouteFingerprint> {
+    hello.routes.iter().find(|server_route| server_route.name == route.name)
+}
+
+fn same_types(route: &RouteFingerprint, other: &RouteFingerprint) -> bool {
+    route.request == other.request && route.response == other.response
+}
+
+/// Fail if the protocol versions differ, and return whether the app versions match.
+fn check_hello(hello: &ServerHello, app_version: &str) -> Result<bool, Error> {
+    if hello.protocol_version != PROTOCOL_VERSION {
+        return Err(Error::IncompatibleProtocol {
+            client: PROTOCOL_VERSION,
+            server: Some(hello.protocol_version),
+        });
+    }
+
+    Ok(hello.app_version == app_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_hello_test() {
+        let mut hello: ServerHello = crate::serde::decode_written_line("", |output| write_hello(output, "1.0.0", vec![]).unwrap());
+
+        assert!(check_hello(&hello, "1.0.0").unwrap());
+        assert!(!check_hello(&hello, "1.1.0").unwrap());
+
+        hello.protocol_version += 1;
+        assert!(matches!(
+            check_hello(&hello, "1.0.0"),
+            Err(Error::IncompatibleProtocol { server: Some(_), .. })
+        ));
+    }
+
+    #[test]
+    fn incompatible_routes_test() {
+        let server_routes = vec![RouteFingerprint::new::<String, u32>("hello"), RouteFingerprint::new::<u32, u32>("count")];
+        let hello: ServerHello = crate::serde::decode_written_line("", |output| write_hello(output, "1.0.0", server_routes).unwrap());
+
+        let client_routes = vec![
+            RouteFingerprint::new::<String, u32>("hello"),
+            RouteFingerprint::new::<u32, String>("count"),
+            RouteFingerprint::new::<u32, u32>("missing"),
+        ];
+        assert_eq!(incompatible_routes(&hello, &client_routes), vec!["count", "missing"]);
+
+        // Evolving routes only need to exist on the server.
+        let client_routes = vec![
+            RouteFingerprint {
+                evolving: true,
+                ..RouteFingerprint::new::<u32, String>("count")
+            },
+            RouteFingerprint {
+                evolving: true,
+                ..RouteFingerprint::new::<u32, u32>("missing")
+            },
+        ];
+        assert_eq!(incompatible_routes(&hello, &client_routes), vec!["missing"]);
+        assert_eq!(evolved_routes(&hello, &client_routes), vec!["count"]);
+    }
+
+    #[test]
+    fn evolving_routes_test() {
+        let routes = vec![
+            RouteFingerprint::new::<String, u32>("hello"),
+            RouteFingerprint {
+                evolving: true,
+                ..RouteFingerprint::new::<u32, u32>("count")
+            },
+        ];
+        assert!(evolving_routes(Encoding::Json, &routes).is_empty());
+
+        #[cfg(feature = "postcard")]
+        assert_eq!(evolving_routes(Encoding::Postcard, &routes), vec!["count"]);
+    }
+
+    #[test]
+    fn negotiate_test() {
+        let mut hello: ServerHello = crate::serde::decode_written_line("", |output| write_hello(output, "1.0.0", vec![]).unwrap());
+
+        // The server binary supports everything the client was built with.
+        for &encoding in Encoding::ALL {
+            let wire = Wire { encoding, ..Wire::default() };
+            assert_eq!(hello.negotiate(wire), wire);
+        }
+
+        for &compression in Compression::ALL {
+            let wire = Wire { compression, ..Wire::default() };
+            assert_eq!(hello.negotiate(wire), wire);
+        }
+        let binary = Wire {
+            framing: Framing::Binary,
+            ..Wire::default()
+        };
+        assert_eq!(hello.negotiate(binary), binary);
+
+        // Servers that are too old to report their capabilities only get the defaults.
+        hello.encodings.clear();
+        hello.compressions.clear();
+        hello.framings.clear();
+        for &encoding in Encoding::ALL {
+            for &compression in Compression::ALL {
+                assert_eq!(hello.negotiate(Wire { encoding, compression, ..Wire::default() }), Wire::default());
+            }
+        }
+        assert_eq!(hello.negotiate(binary), Wire::default());
+    }
+}
@@ -0,0 +1,150 @@
+use std::{io::Write, process::Output, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Error,
+    frame::{FrameKind, Message},
+    ssh::SSH,
+};
+
+/// The argument in place of a route that makes the server answer with a [`ServerInfo`].
+pub const INFO: &str = "--info";
+
+/// What a server binary reports about itself and the machine it runs on.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ServerInfo {
+    /// The version of the crate the server binary was built from.
+    pub app_version: String,
+    /// The version of `beyond` the server binary was built with.
+    pub beyond_version: String,
+    pub hostname: String,
+    /// The architecture the server binary was built for, e.g. `x86_64`.
+    pub arch: String,
+    /// How long the machine has been running, if the OS reports it.
+    pub uptime: Option<Duration>,
+    pub route_count: usize,
+}
+
+/// Answer an `--info` invocation on the server.
+pub fn write_info(output: &mut impl Write, app_version: &str, route_count: usize) -> Result<(), Error> {
+    crate::serde::write_response(
+        output,
+        ServerInfo {
+            app_version: app_version.to_string(),
+            beyond_version: env!("CARGO_PKG_VERSION").to_string(),
+            hostname: hostname(),
+            arch: std::env::consts::ARCH.to_string(),
+            uptime: uptime(),
+            route_count,
+        },
+    )
+}
+
+/// Run the server binary with `command` and check that it is a working `beyond` server.
+pub fn check_server(ssh: &SSH, command: &str) -> Result<ServerInfo, Error> {
+    parse_info(ssh.execute(command)?)
+}
+
+/// Tell apart why the server binary could not answer, based on how the shell ran it.
+fn parse_info(output: Output) -> Result<ServerInfo, Error> {
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+
+    if !output.status.success() {
+        // The dynamic loader fails with the same exit code as a missing command.
+        if stderr.contains("error while loading shared libraries") {
+            return Err(Error::ServerNotExecutable { message: stderr });
+        }
+        return Err(match output.status.code() {
+            // The shell's exit codes for missing commands and commands it cannot execute.
+            Some(127) => Error::ServerComponentNotInstalled,
+            Some(126) => Error::ServerNotExecutable { message: stderr },
+            // Servers from before `--info` treat it as an unknown route.
+            _ if stderr.contains(INFO) => Error::IncompatibleProtocol {
+                client: crate::handshake::PROTOCOL_VERSION,
+                server: None,
+            },
+            _ => Error::ServerWrongProgram { message: stderr },
+        });
+    }
+
+    // Any other program is unlikely to print exactly one valid response.
+    let mut stdout = output.stdout.as_slice();
+    let mut responses = vec![];
+    while let Ok(Some(message)) = crate::frame::read_message(&mut stdout) {
+        match message {
+            Message::Line(line) if !line.starts_with('@') => responses.push(crate::serde::decode_response(line.trim())),
+            Message::Frame(frame) if frame.kind == FrameKind::Payload => responses.push(crate::serde::decode_response_bytes(&frame.marker, frame.body)),
+            _ => {}
+        }
+    }
+
+    match <[_; 1]>::try_from(responses) {
+        Ok([Ok(info)]) => Ok(info),
+        _ => Err(Error::ServerWrongProgram { message: stderr }),
+    }
+}
+
+fn hostname() -> String {
+    if let Ok(hostname) = std::fs::read_to_string("/proc/sys/kernel/hostname") {
+        return hostname.trim().to_string();
+    }
+
+    std::process::Command::new("hostname")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default()
+}
+
+/// The uptime from `/proc/uptime`, which only exists on Linux.
+fn uptime() -> Option<Duration> {
+    let uptime = std::fs::read_to_string("/proc/uptime").ok()?;
+    let seconds: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_secs_f64(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    use super::*;
+
+    fn output(code: i32, stdout: &[u8], stderr: &str) -> Output {
+        Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn parse_info_test() {
+        let mut stdout = vec![];
+        write_info(&mut stdout, "1.0.0", 3).unwrap();
+        let info = parse_info(output(0, &stdout, "")).unwrap();
+        assert_eq!(info.route_count, 3);
+        assert_eq!(info.arch, std::env::consts::ARCH);
+
+        assert!(matches!(
+            parse_info(output(127, b"", "sh: 1: server: not found")),
+            Err(Error::ServerComponentNotInstalled)
+        ));
+        assert!(matches!(
+            parse_info(output(127, b"", "server: error while loading shared libraries: libssl.so.3")),
+            Err(Error::ServerNotExecutable { .. })
+        ));
+        assert!(matches!(
+            parse_info(output(126, b"", "sh: 1: server: Permission denied")),
+            Err(Error::ServerNotExecutable { .. })
+        ));
+        assert!(matches!(
+            parse_info(output(1, b"", "'--info' is not a valid route")),
+            Err(Error::IncompatibleProtocol { server: None, .. })
+        ));
+        assert!(matches!(
+            parse_info(output(0, b"usage: server [OPTIONS]\n", "")),
+            Err(Error::ServerWrongProgram { .. })
+        ));
+    }
+}
@@ -5,10 +5,73 @@
 
 pub use beyond_derive::Beyond;
 
+mod bytes;
+pub use bytes::Bytes;
+
 mod error;
 pub use error::Error;
 
+#[doc(hidden)]
+pub mod client;
+
+#[doc(hidden)]
+pub mod codec;
+pub use codec::Encoding;
+
+#[doc(hidden)]
+pub mod compression;
+pub use compression::Compression;
+
+#[doc(hidden)]
+pub mod deploy;
+pub use deploy::DeployOptions;
+
+pub mod evolution;
+
+#[doc(hidden)]
+pub mod fingerprint;
+
+#[doc(hidden)]
+pub mod frame;
+pub use frame::Framing;
+
+#[doc(hidden)]
+pub mod handshake;
+pub use handshake::ServerHello;
+
+#[doc(hidden)]
+pub mod info;
+pub use info::ServerInfo;
+
+mod exec;
+pub use exec::ExecOptions;
+
+mod fleet;
+pub use fleet::{Fleet, FleetResults, FleetSummary};
+
+#[doc(hidden)]
+pub mod logging;
+pub use logging::LogLevel;
+
+#[doc(hidden)]
+pub mod progress;
+pub use progress::{Progress, report_progress};
+
+#[doc(hidden)]
+pub mod routes;
+pub use routes::{RouteDescription, RouteInfo};
+
+#[doc(hidden)]
+pub mod schema;
+
 #[doc(hidden)]
 pub mod serde;
+pub use serde::{Headers, headers, set_response_header};
 
 pub mod ssh;
+
+mod stream;
+pub use stream::{ReadChunks, ReadChunksError, RequestStream, ResponseStream};
+
+mod transfer;
+pub use transfer::RemoteFile;
@@ -0,0 +1,376 @@
+use serde::{Deserialize, Serialize};
+
+/// The environment variable the client uses to tell the server
+/// which log records to forward.
+pub const LOG_LEVEL_ENV: &str = "BEYOND_LOG";
+
+/// Marks a line on stdout that contains a log record instead of a response,
+/// see `serde::write_control`.
+pub const LOG_PREFIX: &str = "@log ";
+
+/// The verbosity of log records forwarded from the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+
+    fn from_str(level: &str) -> Option<Self> {
+        match level {
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            "trace" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A log record that was emitted by the user logic on the server.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    pub target: String,
+    pub message: String,
+}
+
+/// Forward the log records of the server process to the client, if the client asked for them.
+///
+/// With the `tracing` feature, a global `tracing` subscriber is installed.
+/// With the `log` feature, a global logger is installed. Nothing happens if
+/// the user already installed their own.
+pub fn install() {
+    let Some(level) = std::env::var(LOG_LEVEL_ENV).ok().as_deref().and_then(LogLevel::from_str) else {
+        return;
+    };
+
+    #[cfg(feature = "tracing")]
+    let _ = tracing::subscriber::set_global_default(forward_tracing::Subscriber { level });
+
+    #[cfg(feature = "log")]
+    if log::set_logger(&forward_log::Logger).is_ok() {
+        log::set_max_level(log::LevelFilter::from(level));
+    }
+
+    #[cfg(not(any(feature = "tracing", feature = "log")))]
+    let _ = level;
+}
+
+/// Write a log record to `output`, which is stdout on the server, from where the client picks it up.
+#[cfg_attr(not(any(feature = "tracing", feature = "log")), allow(dead_code))]
+fn write_record(output: &mut impl std::io::Write, record: &LogRecord) {
+    let _ = crate::serde::write_control(output, crate::frame::FrameKind::Log, LOG_PREFIX, record);
+}
+
+/// Re-emit a log record from the server on the client.
+///
+/// With the `tracing` feature, it is emitted as a `tracing` event with the
+/// target `beyond::remote`. Otherwise, with the `log` feature, it is logged
+/// with its original target. The host and route are attached to both.
+pub(crate) fn emit(record: LogRecord, host: &str, route: &str) {
+    #[cfg(feature = "tracing")]
+    {
+        let target = record.target.as_str();
+        let message = record.message.as_str();
+        match record.level {
+            LogLevel::Error => tracing::error!(target: "beyond::remote", host, route, remote_target = target, "{}", message),
+            LogLevel::Warn => tracing::warn!(target: "beyond::remote", host, route, remote_target = target, "{}", message),
+            LogLevel::Info => tracing::info!(target: "beyond::remote", host, route, remote_target = target, "{}", message),
+            LogLevel::Debug => tracing::debug!(target: "beyond::remote", host, route, remote_target = target, "{}", message),
+            LogLevel::Trace => tracing::trace!(target: "beyond::remote", host, route, remote_target = target, "{}", message),
+        }
+    }
+
+    #[cfg(all(feature = "log", not(feature = "tracing")))]
+    log::log!(target: &record.target, log::Level::from(record.level), "[{} {}] {}", host, route, record.message);
+
+    #[cfg(not(any(feature = "tracing", feature = "log")))]
+    let _ = (record, host, route);
+}
+
+/// Report a problem on the client that does not prevent it from working.
+///
+/// It is emitted with `tracing` or `log` under the target `beyond`, depending on
+/// which feature is enabled, and dropped otherwise.
+pub(crate) fn warn(host: &str, message: &str) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(target: "beyond", host, "{}", message);
+
+    #[cfg(all(feature = "log", not(feature = "tracing")))]
+    log::warn!(target: "beyond", "[{}] {}", host, message);
+
+    #[cfg(not(any(feature = "tracing", feature = "log")))]
+    let _ = (host, message);
+}
+
+#[cfg(feature = "tracing")]
+mod forward_tracing {
+    use std::fmt::Write;
+
+    use tracing::{
+        Event, Metadata,
+        field::{Field, Visit},
+        span,
+    };
+
+    use super::{LogLevel, LogRecord};
+
+    impl From<tracing::Level> for LogLevel {
+        fn from(level: tracing::Level) -> Self {
+            match level {
+                tracing::Level::ERROR => LogLevel::Error,
+                tracing::Level::WARN => LogLevel::Warn,
+                tracing::Level::INFO => LogLevel::Info,
+                tracing::Level::DEBUG => LogLevel::Debug,
+                tracing::Level::TRACE => LogLevel::Trace,
+            }
+        }
+    }
+
+    impl From<LogLevel> for tracing::Level {
+        fn from(level: LogLevel) -> Self {
+            match level {
+                LogLevel::Error => tracing::Level::ERROR,
+                LogLevel::Warn => tracing::Level::WARN,
+                LogLevel::Info => tracing::Level::INFO,
+                LogLevel::Debug => tracing::Level::DEBUG,
+                LogLevel::Trace => tracing::Level::TRACE,
+            }
+        }
+    }
+
+    /// A subscriber that forwards events to the client. Spans are ignored.
+    pub(super) struct Subscriber {
+        pub(super) level: LogLevel,
+    }
+
+    impl tracing::Subscriber for Subscriber {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            *metadata.level() <= tracing::Level::from(self.level)
+        }
+
+        fn max_level_hint(&self) -> Option<tracing::level_filters::LevelFilter> {
+            Some(tracing::level_filters::LevelFilter::from_level(self.level.into()))
+        }
+
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut visitor = MessageVisitor::default();
+            event.record(&mut visitor);
+
+            super::write_record(&mut std::io::stdout(), &LogRecord {
+                level: (*event.metadata().level()).into(),
+                target: event.metadata().target().to_string(),
+                message: visitor.message + &visitor.fields,
+            });
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    /// Formats the message of an event followed by its other fields.
+    #[derive(Default)]
+    struct MessageVisitor {
+        message: String,
+        fields: String,
+    }
+
+    impl Visit for MessageVisitor {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.message = format!("{:?}", value);
+            } else {
+                let _ = write!(self.fields, " {}={:?}", field.name(), value);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "log")]
+mod forward_log {
+    use super::{LogLevel, LogRecord};
+
+    impl From<log::Level> for LogLevel {
+        fn from(level: log::Level) -> Self {
+            match level {
+                log::Level::Error => LogLevel::Error,
+                log::Level::Warn => LogLevel::Warn,
+                log::Level::Info => LogLevel::Info,
+                log::Level::Debug => LogLevel::Debug,
+                log::Level::Trace => LogLevel::Trace,
+            }
+        }
+    }
+
+    impl From<LogLevel> for log::Level {
+        fn from(level: LogLevel) -> Self {
+            match level {
+                LogLevel::Error => log::Level::Error,
+                LogLevel::Warn => log::Level::Warn,
+                LogLevel::Info => log::Level::Info,
+                LogLevel::Debug => log::Level::Debug,
+                LogLevel::Trace => log::Level::Trace,
+            }
+        }
+    }
+
+    impl From<LogLevel> for log::LevelFilter {
+        fn from(level: LogLevel) -> Self {
+            log::Level::from(level).to_level_filter()
+        }
+    }
+
+    /// A logger that forwards records to the client.
+    pub(super) struct Logger;
+
+    impl log::Log for Logger {
+        fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+            metadata.level() <= log::max_level()
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+
+            super::write_record(&mut std::io::stdout(), &LogRecord {
+                level: record.level().into(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            });
+        }
+
+        fn flush(&self) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_record_test() {
+        let record = LogRecord {
+            level: LogLevel::Warn,
+            target: "beyond_example".to_string(),
+            message: "disk almost full".to_string(),
+        };
+
+        let written: LogRecord = crate::serde::decode_written_line(LOG_PREFIX, |output| write_record(output, &record));
+        assert_eq!(written, record);
+    }
+
+    #[test]
+    fn log_level_test() {
+        for level in [LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace] {
+            assert_eq!(LogLevel::from_str(level.as_str()), Some(level));
+        }
+        assert_eq!(LogLevel::from_str("verbose"), None);
+
+        // A level includes the records of all levels before it.
+        assert!(LogLevel::Error < LogLevel::Warn);
+        assert!(LogLevel::Debug < LogLevel::Trace);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn forward_tracing_level_test() {
+        let subscriber = forward_tracing::Subscriber { level: LogLevel::Warn };
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(tracing::enabled!(tracing::Level::ERROR));
+            assert!(tracing::enabled!(tracing::Level::WARN));
+            assert!(!tracing::enabled!(tracing::Level::INFO));
+        });
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn emit_test() {
+        use std::sync::{Arc, Mutex};
+
+        use tracing::{
+            field::{Field, Visit},
+            span,
+        };
+
+        /// Records the level, target and fields of every event.
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        impl tracing::Subscriber for Recorder {
+            fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+                span::Id::from_u64(1)
+            }
+
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+            fn event(&self, event: &tracing::Event<'_>) {
+                struct Fields(String);
+                impl Visit for Fields {
+                    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                        self.0 += &format!(" {}={:?}", field.name(), value);
+                    }
+                }
+
+                let mut fields = Fields(format!("{} {}", event.metadata().level(), event.metadata().target()));
+                event.record(&mut fields);
+                self.0.lock().unwrap().push(fields.0);
+            }
+
+            fn enter(&self, _: &span::Id) {}
+
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        let events = Arc::new(Mutex::new(vec![]));
+        tracing::subscriber::with_default(Recorder(events.clone()), || {
+            let record = LogRecord {
+                level: LogLevel::Info,
+                target: "beyond_example".to_string(),
+                message: "started".to_string(),
+            };
+            emit(record, "web", "hello");
+        });
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![r#"INFO beyond::remote message=started host="web" route="hello" remote_target="beyond_example""#]
+        );
+    }
+}
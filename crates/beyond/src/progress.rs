@@ -0,0 +1,61 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+/// The environment variable the client sets when it wants to receive progress updates.
+pub const PROGRESS_ENV: &str = "BEYOND_PROGRESS";
+
+/// Marks a line on stdout that contains a progress update instead of a response,
+/// see `serde::write_control`.
+pub const PROGRESS_PREFIX: &str = "@progress ";
+
+/// A progress update from a long-running route.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Progress {
+    /// How much of the work is done, from `0.0` to `100.0`, if it is known.
+    pub percentage: Option<f32>,
+    /// A description of what is currently happening.
+    pub message: String,
+}
+
+/// Report the progress of the current route to the client.
+///
+/// This does nothing if the client did not ask for progress updates,
+/// or if it is not called while handling a route.
+pub fn report_progress(percentage: impl Into<Option<f32>>, message: impl Into<String>) {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    let enabled = *ENABLED.get_or_init(|| std::env::var_os(PROGRESS_ENV).is_some());
+
+    let progress = Progress {
+        percentage: percentage.into(),
+        message: message.into(),
+    };
+    write_progress(&mut std::io::stdout(), enabled, progress);
+}
+
+/// Write a progress update to `output`, which is stdout on the server, if the client asked for it.
+fn write_progress(output: &mut impl std::io::Write, enabled: bool, progress: Progress) {
+    if enabled {
+        let _ = crate::serde::write_control(output, crate::frame::FrameKind::Progress, PROGRESS_PREFIX, progress);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_progress_test() {
+        let progress = Progress {
+            percentage: Some(42.0),
+            message: "copying".to_string(),
+        };
+
+        let mut output = vec![];
+        write_progress(&mut output, false, progress.clone());
+        assert!(output.is_empty());
+
+        let written: Progress = crate::serde::decode_written_line(PROGRESS_PREFIX, |output| write_progress(output, true, progress.clone()));
+        assert_eq!(written, progress);
+    }
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+
+use crate::handshake::RouteFingerprint;
+
+/// The argument in place of a route that makes the server answer with its routes.
+pub const ROUTES: &str = "--routes";
+
+/// A route as it is known at compile time, from `Server::ROUTES`.
+#[derive(Clone, Copy, Debug)]
+pub struct RouteInfo {
+    pub name: &'static str,
+    /// The name of the request type, as written in `#[beyond_route(...)]`.
+    pub request: &'static str,
+    /// The name of the response type, as written in `#[beyond_route(...)]`.
+    pub response: &'static str,
+    pub stream_request: bool,
+    pub stream_response: bool,
+    /// Whether the types may differ between the client and the server, see [`evolution`](crate::evolution).
+    pub evolving: bool,
+    /// Fingerprints are computed at runtime, so only the functions computing them are stored.
+    pub request_fingerprint: fn() -> u64,
+    pub response_fingerprint: fn() -> u64,
+    /// The JSON Schemas, if the types implement `schemars::JsonSchema` and the `json-schema` feature is enabled.
+    pub request_schema: fn() -> Option<serde_json::Value>,
+    pub response_schema: fn() -> Option<serde_json::Value>,
+}
+
+impl RouteInfo {
+    pub fn fingerprint(&self) -> RouteFingerprint {
+        RouteFingerprint {
+            name: self.name.to_string(),
+            request: (self.request_fingerprint)(),
+            response: (self.response_fingerprint)(),
+            evolving: self.evolving,
+        }
+    }
+
+    pub fn describe(&self) -> RouteDescription {
+        RouteDescription {
+            name: self.name.to_string(),
+            request: self.request.to_string(),
+            response: self.response.to_string(),
+            stream_request: self.stream_request,
+            stream_response: self.stream_response,
+            evolving: self.evolving,
+            request_fingerprint: (self.request_fingerprint)(),
+            response_fingerprint: (self.response_fingerprint)(),
+            request_schema: (self.request_schema)().map(|schema| schema.to_string()),
+            response_schema: (self.response_schema)().map(|schema| schema.to_string()),
+        }
+    }
+}
+
+/// A route of a server binary, as returned by `Client::list_routes`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RouteDescription {
+    pub name: String,
+    /// The name of the request type.
+    pub request: String,
+    /// The name of the response type.
+    pub response: String,
+    pub stream_request: bool,
+    pub stream_response: bool,
+    #[serde(default)]
+    pub evolving: bool,
+    pub request_fingerprint: u64,
+    pub response_fingerprint: u64,
+    /// The JSON Schema of the request as JSON text, which every codec can carry.
+    #[serde(default)]
+    pub request_schema: Option<String>,
+    #[serde(default)]
+    pub response_schema: Option<String>,
+}
+
+/// Answer a `--routes` invocation on the server.
+pub fn write_routes(output: &mut impl std::io::Write, routes: &[RouteInfo]) -> Result<(), crate::Error> {
+    crate::serde::write_response(output, routes.iter().map(RouteInfo::describe).collect::<Vec<_>>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_routes_test() {
+        let routes = [RouteInfo {
+            name: "hello",
+            request: "String",
+            response: "u32",
+            stream_request: false,
+            stream_response: true,
+            evolving: false,
+            request_fingerprint: crate::fingerprint::fingerprint::<String>,
+            response_fingerprint: crate::fingerprint::fingerprint::<u32>,
+            request_schema: || Some(serde_json::json!({ "type": "string" })),
+            response_schema: || None,
+        }];
+
+        let descriptions: Vec<RouteDescription> = crate::serde::decode_written_line("", |output| write_routes(output, &routes).unwrap());
+
+        assert_eq!(descriptions, vec![routes[0].describe()]);
+        assert_eq!(descriptions[0].request_fingerprint, routes[0].fingerprint().request);
+        assert_eq!(routes[0].fingerprint(), RouteFingerprint::new::<String, u32>("hello"));
+
+        // Schemas are sent as JSON text.
+        assert_eq!(descriptions[0].request_schema.as_deref(), Some(r#"{"type":"string"}"#));
+        assert_eq!(descriptions[0].response_schema, None);
+
+        let schema = crate::schema::routes_schema(&routes);
+        assert_eq!(schema["routes"]["hello"]["request"]["type"], "string");
+        assert!(schema["routes"]["hello"]["response"].is_null());
+    }
+}
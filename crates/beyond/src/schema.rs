@@ -0,0 +1,106 @@
+use std::marker::PhantomData;
+
+use crate::RouteInfo;
+
+pub use serde_json::Value;
+
+/// Gets the JSON Schema of `T` in the generated code, if `T` implements
+/// `schemars::JsonSchema` and the `json-schema` feature is enabled.
+///
+/// A proc macro cannot know which traits a type implements, so this uses autoref
+/// specialization: `(&Probe::<T>::new()).json_schema()` resolves to [`WithSchema`]
+/// if it applies, and to [`WithoutSchema`] otherwise.
+pub struct Probe<T>(PhantomData<fn() -> T>);
+
+impl<T> Probe<T> {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+pub trait WithSchema {
+    fn json_schema(&self) -> Option<serde_json::Value>;
+}
+
+#[cfg(feature = "json-schema")]
+impl<T: schemars::JsonSchema> WithSchema for Probe<T> {
+    fn json_schema(&self) -> Option<serde_json::Value> {
+        Some(schemars::schema_for!(T).to_value())
+    }
+}
+
+pub trait WithoutSchema {
+    fn json_schema(&self) -> Option<serde_json::Value>;
+}
+
+impl<T> WithoutSchema for &Probe<T> {
+    fn json_schema(&self) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+/// Describe all routes in one document, with the schemas of their requests and responses
+/// by route name. Types without a schema are `null`.
+pub fn routes_schema(routes: &[RouteInfo]) -> serde_json::Value {
+    let routes = routes
+        .iter()
+        .map(|route| {
+            let schemas = serde_json::json!({
+                "request": (route.request_schema)(),
+                "response": (route.response_schema)(),
+                "stream_request": route.stream_request,
+                "stream_response": route.stream_response,
+            });
+            (route.name.to_string(), schemas)
+        })
+        .collect::<serde_json::Map<_, _>>();
+
+    serde_json::json!({ "routes": routes })
+}
+
+#[cfg(feature = "json-schema")]
+impl schemars::JsonSchema for crate::Bytes {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "Bytes".into()
+    }
+
+    // Bytes are base64 in JSON.
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({
+            "type": "string",
+            "contentEncoding": "base64",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::{Probe, WithSchema as _, WithoutSchema as _};
+
+    #[derive(serde::Deserialize)]
+    #[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+    struct Request {
+        #[allow(dead_code)]
+        name: String,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct NoSchema;
+
+    #[test]
+    // The borrow picks the trait, so it is not needless.
+    #[allow(clippy::needless_borrow)]
+    fn probe_test() {
+        assert_eq!((&Probe::<NoSchema>::new()).json_schema(), None);
+
+        let schema = (&Probe::<Request>::new()).json_schema();
+        if cfg!(feature = "json-schema") {
+            let schema = schema.unwrap();
+            assert_eq!(schema["properties"]["name"]["type"], "string");
+        } else {
+            assert_eq!(schema, None);
+        }
+    }
+}
@@ -1,35 +1,435 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+    time::SystemTime,
+};
+
 use base64::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::Error;
+use crate::{
+    Compression, Encoding, Error,
+    frame::{Frame, FrameKind, Framing, Message},
+};
+
+/// Separates the name of the codec from an encoded payload. `:` never appears in base64.
+pub const CODEC_SEPARATOR: char = ':';
+
+/// Separates the name of the compression from the name of the codec, e.g. in `json+zstd:`.
+pub const COMPRESSION_SEPARATOR: char = '+';
+
+/// Separates the version of the envelope from the codec and compression, e.g. in `json+zstd#1:`.
+pub const ENVELOPE_SEPARATOR: char = '#';
+
+/// The version of the envelope that requests and responses are wrapped in.
+///
+/// It is part of the marker instead of the envelope, so that payloads with
+/// and without an envelope can be told apart before they are decoded.
+pub const ENVELOPE_VERSION: u32 = 1;
+
+/// The metadata that is sent along with a request or response.
+///
+/// The client sends them with every request, see `Client::set_headers`, and the
+/// handler reads them with [`headers`]. The server answers with the request ID
+/// and the headers the handler set with [`set_response_header`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Headers {
+    /// Identifies a call, e.g. in the logs of both sides. The client generates one for every call.
+    pub request_id: Option<String>,
+    /// When the client no longer needs the response.
+    pub deadline: Option<SystemTime>,
+    /// The version of the crate the client was built from.
+    pub client_version: Option<String>,
+    /// The W3C `traceparent` of the caller, to continue its trace on the server.
+    pub trace_context: Option<String>,
+    /// Headers set by the caller or the handler.
+    pub custom: BTreeMap<String, String>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+
+    pub fn deadline(mut self, deadline: SystemTime) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn trace_context(mut self, trace_context: impl Into<String>) -> Self {
+        self.trace_context = Some(trace_context.into());
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.custom.insert(name.into(), value.into());
+        self
+    }
+
+    /// Get a custom header.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.custom.get(name).map(String::as_str)
+    }
+
+    /// Combine these headers with `other`, which takes precedence.
+    pub fn merge(&self, other: &Headers) -> Headers {
+        let mut custom = self.custom.clone();
+        custom.extend(other.custom.clone());
+
+        Headers {
+            request_id: other.request_id.clone().or_else(|| self.request_id.clone()),
+            deadline: other.deadline.or(self.deadline),
+            client_version: other.client_version.clone().or_else(|| self.client_version.clone()),
+            trace_context: other.trace_context.clone().or_else(|| self.trace_context.clone()),
+            custom,
+        }
+    }
+}
+
+/// Wraps a request or response together with its headers.
+#[derive(Deserialize, Serialize)]
+struct Envelope<T> {
+    headers: Headers,
+    body: T,
+}
+
+/// The headers of the last request the server process decoded, or `None`
+/// if the client is too old to send an envelope.
+static REQUEST_HEADERS: Mutex<Option<Headers>> = Mutex::new(None);
+
+/// The custom headers the handler set for its responses.
+static RESPONSE_HEADERS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// Lock a mutex, even if a panicking handler poisoned it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Get the headers of the request that is being handled.
+///
+/// They are empty if it is not called while handling a route, or if the client is too old to send headers.
+pub fn headers() -> Headers {
+    lock(&REQUEST_HEADERS).clone().unwrap_or_default()
+}
+
+/// Set a custom header on the responses of the route that is being handled.
+pub fn set_response_header(name: impl Into<String>, value: impl Into<String>) {
+    lock(&RESPONSE_HEADERS).insert(name.into(), value.into());
+}
+
+/// The headers of the responses of the server process, or `None` if the
+/// client did not send an envelope and would not understand one.
+fn response_headers() -> Option<Headers> {
+    let request_headers = lock(&REQUEST_HEADERS);
+    let request_id = request_headers.as_ref()?.request_id.clone();
+
+    Some(Headers {
+        request_id,
+        custom: lock(&RESPONSE_HEADERS).clone(),
+        ..Headers::default()
+    })
+}
 
 pub fn encode_request<R: Serialize>(request: R) -> Result<String, Error> {
-    let json_request = serde_json::to_string(&request).map_err(Error::SerializeRequest)?;
-    let base64_request = BASE64_STANDARD.encode(json_request);
-    Ok(base64_request)
+    encode_request_with(Encoding::Json, Compression::None, None, request)
+}
+
+/// Encode a request, wrapped in an envelope with `headers` if there are any.
+pub fn encode_request_with<R: Serialize>(encoding: Encoding, compression: Compression, headers: Option<&Headers>, request: R) -> Result<String, Error> {
+    let (marker, request) = encode_payload(encoding, compression, headers, request, Error::SerializeRequest)?;
+    Ok(to_payload(&marker, &request))
 }
 
+pub fn encode_request_frame<R: Serialize>(encoding: Encoding, compression: Compression, headers: Option<&Headers>, request: R) -> Result<Frame, Error> {
+    let (marker, body) = encode_payload(encoding, compression, headers, request, Error::SerializeRequest)?;
+    Ok(Frame {
+        kind: FrameKind::Payload,
+        marker,
+        body,
+    })
+}
+
+/// Encode a response in the encoding and with the compression the client asked for.
+///
+/// It is wrapped in an envelope if the request was.
 pub fn encode_response<R: Serialize>(response: R) -> Result<String, Error> {
-    let json_response = serde_json::to_string(&response).map_err(Error::SerializeResponse)?;
-    let base64_response = BASE64_STANDARD.encode(json_response);
-    Ok(base64_response)
+    let (marker, response) = encode_response_bytes(response)?;
+    Ok(to_payload(&marker, &response))
+}
+
+/// Encode a response without an envelope.
+pub fn encode_response_with<R: Serialize>(encoding: Encoding, compression: Compression, response: R) -> Result<String, Error> {
+    let (marker, response) = encode_payload(encoding, compression, None, response, Error::SerializeResponse)?;
+    Ok(to_payload(&marker, &response))
+}
+
+/// Encode a response like [`encode_response`], but without base64.
+fn encode_response_bytes<R: Serialize>(response: R) -> Result<(String, Vec<u8>), Error> {
+    encode_payload(Encoding::from_env(), Compression::from_env(), response_headers().as_ref(), response, Error::SerializeResponse)
 }
 
-pub fn decode_request<R: for<'a> Deserialize<'a>>(base64_request: &str) -> Result<R, Error> {
-    let json_request = String::from_utf8_lossy(&BASE64_STANDARD.decode(base64_request).map_err(Error::Base64DecodeRequest)?).to_string();
-    let request = serde_json::from_str(&json_request).map_err(Error::DeserializeRequest)?;
+/// Encode a payload, wrap it in an envelope if there are headers, compress it if it
+/// is large, and get the marker with the names of its codec and compression.
+fn encode_payload<T: Serialize>(
+    encoding: Encoding,
+    compression: Compression,
+    headers: Option<&Headers>,
+    value: T,
+    json_error: fn(serde_json::Error) -> Error,
+) -> Result<(String, Vec<u8>), Error> {
+    let bytes = match headers {
+        Some(headers) => encoding.encode(
+            &Envelope {
+                headers: headers.clone(),
+                body: value,
+            },
+            json_error,
+        )?,
+        None => encoding.encode(&value, json_error)?,
+    };
+    let (compression, bytes) = compression.compress(bytes)?;
+
+    let mut marker = if compression != Compression::None {
+        format!("{}{}{}", encoding.name(), COMPRESSION_SEPARATOR, compression.name())
+    } else if encoding != Encoding::Json || headers.is_some() {
+        encoding.name().to_string()
+    } else {
+        // Uncompressed JSON payloads are not marked, so that sides from before codecs can read them.
+        String::new()
+    };
+    if headers.is_some() {
+        marker = format!("{}{}{}", marker, ENVELOPE_SEPARATOR, ENVELOPE_VERSION);
+    }
+
+    Ok((marker, bytes))
+}
+
+/// Encode the bytes with base64 and put the marker in front of them.
+fn to_payload(marker: &str, bytes: &[u8]) -> String {
+    if marker.is_empty() {
+        return BASE64_STANDARD.encode(bytes);
+    }
+    format!("{}{}{}", marker, CODEC_SEPARATOR, BASE64_STANDARD.encode(bytes))
+}
+
+/// Split a payload into its marker and the base64 encoded bytes.
+fn from_payload(payload: &str) -> (&str, &str) {
+    payload.split_once(CODEC_SEPARATOR).unwrap_or(("", payload))
+}
+
+/// Split a payload into its marker and the bytes it encodes.
+pub(crate) fn payload_bytes(payload: &str, base64_error: fn(base64::DecodeError) -> Error) -> Result<(&str, Vec<u8>), Error> {
+    let (marker, base64_payload) = from_payload(payload);
+    Ok((marker, BASE64_STANDARD.decode(base64_payload).map_err(base64_error)?))
+}
+
+/// Get the codec, the compression and whether there is an envelope from the marker of a payload.
+fn parse_marker(marker: &str) -> Result<(Encoding, Compression, bool), Error> {
+    let (marker, envelope) = match marker.split_once(ENVELOPE_SEPARATOR) {
+        Some((marker, version)) if version == ENVELOPE_VERSION.to_string() => (marker, true),
+        Some((_, version)) => return Err(Error::UnsupportedEnvelope { version: version.to_string() }),
+        None => (marker, false),
+    };
+
+    if marker.is_empty() {
+        return Ok((Encoding::Json, Compression::None, envelope));
+    }
+
+    let (encoding, compression) = match marker.split_once(COMPRESSION_SEPARATOR) {
+        Some((encoding, compression)) => (encoding, compression),
+        None => (marker, Compression::None.name()),
+    };
+    let encoding = Encoding::from_name(encoding).ok_or_else(|| Error::UnsupportedCodec { codec: encoding.to_string() })?;
+    let compression = Compression::from_name(compression).ok_or_else(|| Error::UnsupportedCompression {
+        compression: compression.to_string(),
+    })?;
+
+    Ok((encoding, compression, envelope))
+}
+
+/// Decode a payload, and take the headers out of its envelope if it has one.
+fn decode_payload<T: for<'a> Deserialize<'a>>(marker: &str, bytes: Vec<u8>, json_error: fn(serde_json::Error) -> Error) -> Result<(T, Option<Headers>), Error> {
+    let (encoding, compression, envelope) = parse_marker(marker)?;
+    let bytes = compression.decompress(bytes)?;
+
+    if envelope {
+        let envelope: Envelope<T> = encoding.decode(&bytes, json_error)?;
+        Ok((envelope.body, Some(envelope.headers)))
+    } else {
+        Ok((encoding.decode(&bytes, json_error)?, None))
+    }
+}
+
+/// Encoded responses larger than this are written to a temporary file on the
+/// server instead of stdout, and the client fetches them over SFTP.
+pub const SPILL_THRESHOLD: usize = 1024 * 1024;
+
+/// Marks a line on stdout that contains the path of a spilled response
+/// instead of the response itself, see `write_control`.
+pub const SPILL_PREFIX: &str = "@spill ";
+
+/// Write a response in the framing the client asked for.
+pub fn write_response<W: Write, R: Serialize>(output: &mut W, response: R) -> Result<(), Error> {
+    match Framing::from_env() {
+        Framing::Text => {
+            let encoded_response = encode_response(response)?;
+
+            if encoded_response.len() > SPILL_THRESHOLD && can_spill() {
+                let path = spill_response(encoded_response.as_bytes()).map_err(Error::WriteResponse)?;
+                writeln!(output, "{}{}", SPILL_PREFIX, path.display()).map_err(Error::WriteResponse)?;
+            } else {
+                writeln!(output, "{}", encoded_response).map_err(Error::WriteResponse)?;
+            }
+        }
+        Framing::Binary => {
+            let (marker, body) = encode_response_bytes(response)?;
+
+            let frame = if body.len() > SPILL_THRESHOLD && can_spill() {
+                let path = spill_response(&body).map_err(Error::WriteResponse)?;
+                Frame {
+                    kind: FrameKind::Spill,
+                    marker,
+                    body: path.to_string_lossy().into_owned().into_bytes(),
+                }
+            } else {
+                Frame {
+                    kind: FrameKind::Payload,
+                    marker,
+                    body,
+                }
+            };
+            crate::frame::write_frame(output, &frame).map_err(Error::WriteResponse)?;
+        }
+    }
+
+    output.flush().map_err(Error::WriteResponse)?;
+    Ok(())
+}
+
+/// Write a log record or progress update in between the responses, in the framing the client asked for.
+///
+/// With text framing, it is a line that starts with `prefix`. Prefixes start with `@`,
+/// which never appears in base64, so these lines cannot be mistaken for responses.
+pub(crate) fn write_control<W: Write, R: Serialize>(output: &mut W, kind: FrameKind, prefix: &str, value: R) -> Result<(), Error> {
+    // Control messages are never wrapped in an envelope, they are not part of a call.
+    let (marker, body) = encode_payload(Encoding::from_env(), Compression::from_env(), None, value, Error::SerializeResponse)?;
+
+    // The message is written in one go, so that it does not interleave with other output.
+    let message = match Framing::from_env() {
+        Framing::Text => format!("{}{}\n", prefix, to_payload(&marker, &body)).into_bytes(),
+        Framing::Binary => crate::frame::encode_frame(&Frame { kind, marker, body }).map_err(Error::WriteResponse)?,
+    };
+
+    output.write_all(&message).map_err(Error::WriteResponse)?;
+    output.flush().map_err(Error::WriteResponse)
+}
+
+/// Whether large responses can be spilled to a file for the client to fetch over SFTP.
+///
+/// A route that runs as another user through sudo writes them to stdout instead,
+/// since the SSH user could not read a private file of that user.
+fn can_spill() -> bool {
+    std::env::var_os("SUDO_UID").is_none()
+}
+
+/// Write a spilled response to a temporary file that only the current user can read.
+fn spill_response(encoded_response: &[u8]) -> std::io::Result<std::path::PathBuf> {
+    let path = std::env::temp_dir().join(crate::transfer::temporary_file_name(Path::new("response")));
+
+    let mut file = crate::transfer::create_private_file(&path)?;
+    file.write_all(encoded_response)?;
+    Ok(path)
+}
+
+pub fn decode_request<R: for<'a> Deserialize<'a>>(encoded_request: &str) -> Result<R, Error> {
+    let (marker, request) = payload_bytes(encoded_request, Error::Base64DecodeRequest)?;
+    decode_request_bytes(marker, request)
+}
+
+pub fn decode_request_bytes<R: for<'a> Deserialize<'a>>(marker: &str, request: Vec<u8>) -> Result<R, Error> {
+    Ok(decode_request_with_headers(marker, request)?.0)
+}
+
+/// Decode a request, and get the headers from its envelope if it has one.
+pub fn decode_request_with_headers<R: for<'a> Deserialize<'a>>(marker: &str, request: Vec<u8>) -> Result<(R, Option<Headers>), Error> {
+    decode_payload(marker, request, Error::DeserializeRequest)
+}
+
+/// Decode a request the server process received, as a line of text or a frame,
+/// and remember its headers for [`headers`].
+pub(crate) fn decode_request_message<R: for<'a> Deserialize<'a>>(message: Message) -> Result<R, Error> {
+    let (request, headers) = match message {
+        Message::Line(line) => {
+            let (marker, request) = payload_bytes(line.trim(), Error::Base64DecodeRequest)?;
+            decode_request_with_headers(marker, request)?
+        }
+        Message::Frame(Frame {
+            kind: FrameKind::Payload,
+            marker,
+            body,
+        }) => decode_request_with_headers(&marker, body)?,
+        Message::Frame(_) => {
+            return Err(Error::ReadRequest(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "expected a request, but got another kind of frame",
+            )));
+        }
+    };
+
+    if let Some(headers) = headers {
+        *lock(&REQUEST_HEADERS) = Some(headers);
+    }
     Ok(request)
 }
 
-pub fn decode_response<R: for<'a> Deserialize<'a>>(base64_response: &str) -> Result<R, Error> {
-    let json_response = String::from_utf8_lossy(&BASE64_STANDARD.decode(base64_response).map_err(Error::Base64DecodeResponse)?).to_string();
-    let response = serde_json::from_str(&json_response).map_err(Error::DeserializeResponse)?;
-    Ok(response)
+/// Decode the request of a route with a single request.
+///
+/// It is passed as a command-line argument with text framing, and written to stdin otherwise.
+pub fn read_request<R: for<'a> Deserialize<'a>>(encoded_request: &str) -> Result<R, Error> {
+    if !encoded_request.is_empty() {
+        return decode_request_message(Message::Line(encoded_request.to_string()));
+    }
+
+    match crate::frame::read_message(&mut std::io::stdin().lock()).map_err(Error::ReadRequest)? {
+        Some(message) => decode_request_message(message),
+        None => Err(Error::ReadRequest(std::io::ErrorKind::UnexpectedEof.into())),
+    }
+}
+
+/// Decode the line `write` writes with text framing, after removing `prefix`.
+#[cfg(test)]
+pub(crate) fn decode_written_line<R: for<'a> Deserialize<'a>>(prefix: &str, write: impl FnOnce(&mut Vec<u8>)) -> R {
+    let mut output = vec![];
+    write(&mut output);
+    let line = String::from_utf8(output).unwrap();
+    decode_response(line.strip_prefix(prefix).unwrap().strip_suffix('\n').unwrap()).unwrap()
+}
+
+pub fn decode_response<R: for<'a> Deserialize<'a>>(encoded_response: &str) -> Result<R, Error> {
+    let (marker, response) = payload_bytes(encoded_response, Error::Base64DecodeResponse)?;
+    decode_response_bytes(marker, response)
+}
+
+pub fn decode_response_bytes<R: for<'a> Deserialize<'a>>(marker: &str, response: Vec<u8>) -> Result<R, Error> {
+    Ok(decode_response_with_headers(marker, response)?.0)
+}
+
+/// Decode a response, and get the headers from its envelope if it has one.
+pub fn decode_response_with_headers<R: for<'a> Deserialize<'a>>(marker: &str, response: Vec<u8>) -> Result<(R, Option<Headers>), Error> {
+    decode_payload(marker, response, Error::DeserializeResponse)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compression::COMPRESSION_THRESHOLD;
 
     #[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
     struct Request {
@@ -64,4 +464,123 @@ mod tests {
 
         assert_eq!(response, decoded_response);
     }
+
+    #[test]
+    fn serde_encoding_test() {
+        let request = Request {
+            name: "Bob".to_string(),
+        };
+
+        // JSON payloads stay readable for sides from before codecs.
+        assert!(!encode_request(request.clone()).unwrap().contains(CODEC_SEPARATOR));
+
+        for &encoding in Encoding::ALL {
+            let encoded_request = encode_request_with(encoding, Compression::None, None, request.clone()).unwrap();
+            let decoded_request: Request = decode_request(&encoded_request).unwrap();
+            assert_eq!(request, decoded_request);
+        }
+
+        assert!(matches!(
+            decode_request::<Request>("unknown:AAAA"),
+            Err(Error::UnsupportedCodec { codec }) if codec == "unknown"
+        ));
+    }
+
+    #[test]
+    fn serde_compression_test() {
+        let small = Response {
+            message: "Hello, Bob!".to_string(),
+        };
+        let large = Response {
+            message: "Hello, Bob! ".repeat(COMPRESSION_THRESHOLD),
+        };
+
+        for &compression in Compression::ALL {
+            // Small payloads are never compressed.
+            let encoded_response = encode_response_with(Encoding::Json, compression, small.clone()).unwrap();
+            assert!(!encoded_response.contains(CODEC_SEPARATOR));
+
+            let encoded_response = encode_response_with(Encoding::Json, compression, large.clone()).unwrap();
+            if compression != Compression::None {
+                assert!(encoded_response.starts_with(&format!("json+{}:", compression.name())));
+                assert!(encoded_response.len() < large.message.len() / 10);
+            }
+            let decoded_response: Response = decode_response(&encoded_response).unwrap();
+            assert_eq!(large, decoded_response);
+        }
+
+        assert!(matches!(
+            decode_response::<Response>("json+unknown:AAAA"),
+            Err(Error::UnsupportedCompression { compression }) if compression == "unknown"
+        ));
+    }
+
+    #[test]
+    fn serde_envelope_test() {
+        let request = Request {
+            name: "Bob".to_string(),
+        };
+        let headers = Headers::new()
+            .request_id("42")
+            .deadline(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(60))
+            .trace_context("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .header("tenant", "acme");
+
+        for &encoding in Encoding::ALL {
+            let encoded_request = encode_request_with(encoding, Compression::None, Some(&headers), request.clone()).unwrap();
+            assert!(encoded_request.starts_with(&format!("{}#{}:", encoding.name(), ENVELOPE_VERSION)));
+
+            let (marker, bytes) = payload_bytes(&encoded_request, Error::Base64DecodeRequest).unwrap();
+            assert_eq!(decode_request_with_headers::<Request>(marker, bytes).unwrap(), (request.clone(), Some(headers.clone())));
+
+            // Only the requests the server process receives are remembered for `headers`,
+            // so that decoding payloads elsewhere never changes them.
+            let decoded_request: Request = decode_request_message(Message::Line(encoded_request)).unwrap();
+            assert_eq!(request, decoded_request);
+            assert_eq!(super::headers(), headers);
+        }
+
+        // The response echoes the request ID and carries the headers set by the handler.
+        set_response_header("cache", "hit");
+        let response = Response {
+            message: "Hello, Bob!".to_string(),
+        };
+        let encoded_response = encode_response(response.clone()).unwrap();
+        let (marker, bytes) = payload_bytes(&encoded_response, Error::Base64DecodeResponse).unwrap();
+        let (decoded_response, response_headers) = decode_response_with_headers::<Response>(marker, bytes).unwrap();
+        assert_eq!(response, decoded_response);
+        assert_eq!(response_headers, Some(Headers::new().request_id("42").header("cache", "hit")));
+
+        // Payloads without an envelope have no headers.
+        let encoded_response = encode_response_with(Encoding::Json, Compression::None, response).unwrap();
+        let (marker, bytes) = payload_bytes(&encoded_response, Error::Base64DecodeResponse).unwrap();
+        assert_eq!(decode_response_with_headers::<Response>(marker, bytes).unwrap().1, None);
+
+        assert!(matches!(
+            decode_request::<Request>("json#2:AAAA"),
+            Err(Error::UnsupportedEnvelope { version }) if version == "2"
+        ));
+
+        // Leave no headers behind for the responses other tests encode.
+        *lock(&REQUEST_HEADERS) = None;
+        lock(&RESPONSE_HEADERS).clear();
+    }
+
+    #[test]
+    fn spill_response_test() {
+        let response = Response {
+            message: "a".repeat(SPILL_THRESHOLD),
+        };
+
+        let mut output = vec![];
+        write_response(&mut output, response.clone()).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let path = output.trim().strip_prefix(SPILL_PREFIX).unwrap();
+        let encoded_response = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let decoded_response: Response = decode_response(&encoded_response).unwrap();
+        assert_eq!(response, decoded_response);
+    }
 }
@@ -1,57 +1,798 @@
-use std::{io::Read, process::ExitStatus};
+use std::{
+    fs::OpenOptions,
+    io::{Read, Seek, SeekFrom, Write},
+    net::{Ipv4Addr, Shutdown, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    time::Duration,
+};
 
-use ssh::Session;
+use base64::prelude::*;
+use ssh2::{BlockDirections, Channel, CheckResult, ErrorCode, KnownHostFileKind, OpenFlags, OpenType, RenameFlags, Session};
 
 use crate::Error;
 
+mod config;
+pub use config::HostConfig;
+
+/// An SSH session that commands can be executed on.
+///
+/// It can be shared between threads. Every command runs on its own channel,
+/// so multiple commands can run at the same time.
+#[derive(Clone)]
 pub struct SSH {
     session: Session,
+    waiter: Waiter,
+    destination: String,
 }
 
 impl SSH {
+    /// Connect to `destination`, which has the form `[user@]host[:port]`.
+    ///
+    /// Like `ssh`, the host is looked up in the SSH config with `ssh -G` and
+    /// the SSH agent, the configured identity files and the default keys
+    /// are used for authentication. If the host has a `ProxyJump`, the
+    /// connection goes through those jump hosts, and if it has a
+    /// `ProxyCommand`, through that command.
     pub fn new(destination: &str) -> Result<Self, Error> {
-        let mut session = Session::new().map_err(|_| Error::SSHSessionCreate)?;
-        session.set_host(destination).map_err(Error::SSHSetHost)?;
-        session.parse_config(None).map_err(Error::SSHConfigParse)?;
-        session.connect().map_err(Error::SSHConnect)?;
-        session
-            .userauth_publickey_auto(None)
-            .map_err(Error::SSHAuth)?;
+        Self::connect(&Hop::new(destination), &[])
+    }
+
+    /// Connect to `target` through the `jump_hosts`, in the order they are given.
+    ///
+    /// Every host is reached through a tunnel over the session to the previous
+    /// one, so only the first jump host has to be reachable directly. If no jump
+    /// hosts are given, the ones from `ProxyJump` in the SSH config are used,
+    /// with the host key policy of the target.
+    pub fn connect(target: &Hop, jump_hosts: &[Hop]) -> Result<Self, Error> {
+        let (_, host, _) = parse_destination(&target.destination);
+        let config = HostConfig::load(host)?;
+
+        let configured_jump_hosts: Vec<Hop>;
+        let jump_hosts = match (jump_hosts, &config.proxy_jump) {
+            ([], Some(proxy_jump)) => {
+                configured_jump_hosts = proxy_jump
+                    .iter()
+                    .map(|jump_host| Hop::new(jump_host).host_key_policy(target.host_key_policy))
+                    .collect();
+                &configured_jump_hosts
+            }
+            _ => jump_hosts,
+        };
+
+        let mut previous: Option<SSH> = None;
+        for jump_host in jump_hosts {
+            let (_, host, _) = parse_destination(&jump_host.destination);
+            previous = Some(Self::connect_hop(jump_host, HostConfig::load(host)?, previous.as_ref())?);
+        }
+
+        Self::connect_hop(target, config, previous.as_ref())
+    }
+
+    /// Connect to a single host, either directly or through the session to the previous hop.
+    fn connect_hop(hop: &Hop, config: HostConfig, previous: Option<&SSH>) -> Result<Self, Error> {
+        let (user, host, port) = parse_destination(&hop.destination);
+
+        let hostname = config.hostname.as_deref().unwrap_or(host);
+        let port = port.or(config.port).unwrap_or(22);
+        let user = user
+            .map(str::to_string)
+            .or(config.user)
+            .or_else(|| std::env::var("USER").ok())
+            .unwrap_or_else(|| "root".to_string());
+
+        let mut session = Session::new().map_err(Error::SSHSessionCreate)?;
+        let stream = match (previous, &config.proxy_command) {
+            (Some(previous), _) => previous.tunnel(hostname, port)?,
+            (None, Some(proxy_command)) => proxy(&config::expand_tokens(proxy_command, host, hostname, port, &user))?,
+            (None, None) => TcpStream::connect((hostname, port)).map_err(Error::SSHTcpConnect)?,
+        };
+        let waiter = Waiter::new(&session, &stream);
+        session.set_tcp_stream(stream);
+        session.handshake().map_err(Error::SSHConnect)?;
+
+        check_known_host(&session, hostname, port, hop.host_key_policy)?;
+        let identity_files: Vec<PathBuf> = hop.identity_files.iter().chain(&config.identity_files).cloned().collect();
+        authenticate(&session, &user, &identity_files, hop.agent)?;
+
+        // ssh2 locks the session for the whole duration of a call, so in blocking mode
+        // a read on one channel would stall all other channels. In non-blocking mode,
+        // every call returns immediately and is retried by this module instead.
+        session.set_blocking(false);
 
-        Ok(Self { session })
+        Ok(Self {
+            session,
+            waiter,
+            destination: hop.destination.clone(),
+        })
+    }
+
+    /// Open a connection from the server to `host:port`.
+    ///
+    /// The data is forwarded between the returned socket and a direct-tcpip
+    /// channel by background threads, which stop once either side is closed.
+    fn tunnel(&self, host: &str, port: u16) -> Result<TcpStream, Error> {
+        let channel = self.waiter.retry(|| self.session.channel_direct_tcpip(host, port, None)).map_err(Error::SSHTunnel)?;
+        let (local, remote) = socket_pair().map_err(Error::SSHTcpConnect)?;
+
+        let waiter = self.waiter.clone();
+        std::thread::spawn(move || forward(channel, remote, waiter));
+
+        Ok(local)
+    }
+
+    /// The destination this session is connected to, as it was passed to [`SSH::new`] or [`Hop::new`].
+    pub fn destination(&self) -> &str {
+        &self.destination
     }
 
     pub fn execute(
-        &mut self,
+        &self,
         command: &str,
     ) -> Result<std::process::Output, Error> {
-        let mut channel = self
-            .session
-            .channel_new()
-            .map_err(Error::SSHChannelCreate)?;
-        channel.open_session().map_err(Error::SSHChannelOpen)?;
-        channel.request_exec(command.as_bytes()).map_err(Error::SSHExecute)?;
-        channel.send_eof().map_err(Error::SSHExecute)?;
-
-        let raw_exit_status = channel.get_exit_status().ok_or(Error::SSHCommandStoppedBySignal)?;
-        let exit_status;
-        if cfg!(unix) {
-            use std::os::unix::process::ExitStatusExt;
-            exit_status  = ExitStatus::from_raw(raw_exit_status);
-        } else {
-            unimplemented!()
+        self.execute_streaming(command)?.wait_with_output()
+    }
+
+    /// Execute a command without waiting for it to finish.
+    ///
+    /// The returned [`RemoteProcess`] can be used to read stdout
+    /// while the command is still running.
+    pub fn execute_streaming(&self, command: &str) -> Result<RemoteProcess, Error> {
+        let mut process = self.spawn(command)?;
+        process.close_stdin()?;
+        Ok(process)
+    }
+
+    /// Execute a command and keep its stdin open.
+    ///
+    /// Writing to the returned [`RemoteProcess`] writes to the stdin of the
+    /// command. [`RemoteProcess::close_stdin`] has to be called once
+    /// everything is written.
+    pub fn spawn(&self, command: &str) -> Result<RemoteProcess, Error> {
+        let mut channel = self.waiter.retry(|| self.session.channel_session()).map_err(Error::SSHChannelCreate)?;
+        self.waiter.retry(|| channel.exec(command)).map_err(Error::SSHExecute)?;
+
+        Ok(RemoteProcess {
+            channel,
+            waiter: self.waiter.clone(),
+        })
+    }
+
+    /// Upload a local file to the server over SFTP.
+    pub fn upload(&self, local: &Path, remote: &Path) -> Result<(), Error> {
+        self.upload_with(local, remote, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE, 0o644)
+    }
+
+    /// Upload a local file to a new file on the server that only the SSH user can read.
+    ///
+    /// Fails if the file already exists, so that a temporary file cannot be prepared by someone else.
+    pub(crate) fn upload_new(&self, local: &Path, remote: &Path) -> Result<(), Error> {
+        self.upload_with(local, remote, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE, 0o600)
+    }
+
+    fn upload_with(&self, local: &Path, remote: &Path, flags: OpenFlags, mode: i32) -> Result<(), Error> {
+        let mut local_file = std::fs::File::open(local).map_err(Error::SSHFileTransfer)?;
+
+        let sftp = self.waiter.retry(|| self.session.sftp()).map_err(Error::SSHSftp)?;
+        let remote_file = self.waiter.retry(|| sftp.open_mode(remote, flags, mode, OpenType::File)).map_err(Error::SSHSftp)?;
+        std::io::copy(&mut local_file, &mut Retrying(remote_file, &self.waiter)).map_err(Error::SSHFileTransfer)?;
+
+        Ok(())
+    }
+
+    /// Download a file from the server over SFTP.
+    pub fn download(&self, remote: &Path, local: &Path) -> Result<(), Error> {
+        self.download_with(remote, || std::fs::File::create(local))
+    }
+
+    /// Download a file from the server to a new local file that only the current user can read.
+    pub(crate) fn download_new(&self, remote: &Path, local: &Path) -> Result<(), Error> {
+        self.download_with(remote, || crate::transfer::create_private_file(local))
+    }
+
+    fn download_with(&self, remote: &Path, create: impl FnOnce() -> std::io::Result<std::fs::File>) -> Result<(), Error> {
+        let sftp = self.waiter.retry(|| self.session.sftp()).map_err(Error::SSHSftp)?;
+        let mut remote_file = Retrying(self.waiter.retry(|| sftp.open(remote)).map_err(Error::SSHSftp)?, &self.waiter);
+
+        let mut local_file = create().map_err(Error::SSHFileTransfer)?;
+        std::io::copy(&mut remote_file, &mut local_file).map_err(Error::SSHFileTransfer)?;
+
+        Ok(())
+    }
+
+    /// Read the contents of a file on the server over SFTP.
+    pub fn read(&self, remote: &Path) -> Result<Vec<u8>, Error> {
+        let sftp = self.waiter.retry(|| self.session.sftp()).map_err(Error::SSHSftp)?;
+        let mut remote_file = Retrying(self.waiter.retry(|| sftp.open(remote)).map_err(Error::SSHSftp)?, &self.waiter);
+
+        let mut contents = vec![];
+        remote_file.read_to_end(&mut contents).map_err(Error::SSHFileTransfer)?;
+
+        Ok(contents)
+    }
+
+    /// Set the permissions of a file on the server over SFTP.
+    pub fn set_permissions(&self, remote: &Path, mode: u32) -> Result<(), Error> {
+        let sftp = self.waiter.retry(|| self.session.sftp()).map_err(Error::SSHSftp)?;
+        let stat = ssh2::FileStat {
+            size: None,
+            uid: None,
+            gid: None,
+            perm: Some(mode),
+            atime: None,
+            mtime: None,
+        };
+        self.waiter.retry(|| sftp.setstat(remote, stat.clone())).map_err(Error::SSHSftp)
+    }
+
+    /// Move a file on the server over SFTP, replacing the file at `to` if there is one.
+    ///
+    /// An overwriting rename is tried first. Servers like OpenSSH refuse to overwrite
+    /// with SFTP, so the old file is removed first for them, and `to` is missing in between.
+    pub fn rename(&self, from: &Path, to: &Path) -> Result<(), Error> {
+        let sftp = self.waiter.retry(|| self.session.sftp()).map_err(Error::SSHSftp)?;
+        let overwrite = RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE;
+        if self.waiter.retry(|| sftp.rename(from, to, Some(overwrite))).is_ok() {
+            return Ok(());
         }
 
-        let mut stdout = vec![];
-        channel.stdout().read_to_end(&mut stdout).map_err(Error::SSHReadStdout)?;
+        let _ = self.waiter.retry(|| sftp.unlink(to));
+        self.waiter.retry(|| sftp.rename(from, to, Some(RenameFlags::NATIVE))).map_err(Error::SSHSftp)
+    }
+
+    /// Delete a file on the server over SFTP.
+    pub fn remove(&self, remote: &Path) -> Result<(), Error> {
+        let sftp = self.waiter.retry(|| self.session.sftp()).map_err(Error::SSHSftp)?;
+        self.waiter.retry(|| sftp.unlink(remote)).map_err(Error::SSHSftp)
+    }
+}
+
+/// A host to connect to, either the target or a jump host on the way to it.
+#[derive(Clone, Debug)]
+pub struct Hop {
+    destination: String,
+    identity_files: Vec<PathBuf>,
+    agent: bool,
+    host_key_policy: HostKeyPolicy,
+}
+
+impl Hop {
+    /// `destination` has the form `[user@]host[:port]` and is looked up in `~/.ssh/config`.
+    pub fn new(destination: impl Into<String>) -> Self {
+        Self {
+            destination: destination.into(),
+            identity_files: vec![],
+            agent: true,
+            host_key_policy: HostKeyPolicy::default(),
+        }
+    }
+
+    /// Try `path` before the configured identity files and the default keys.
+    pub fn identity_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.identity_files.push(path.into());
+        self
+    }
+
+    /// Whether to try the SSH agent first. It is used by default.
+    pub fn agent(mut self, agent: bool) -> Self {
+        self.agent = agent;
+        self
+    }
 
+    /// What to do if the host is not in `~/.ssh/known_hosts` yet.
+    pub fn host_key_policy(mut self, policy: HostKeyPolicy) -> Self {
+        self.host_key_policy = policy;
+        self
+    }
+}
+
+/// What to do with a host that is not in `~/.ssh/known_hosts`, like `StrictHostKeyChecking` of `ssh`.
+///
+/// A host whose key does not match the known one is always rejected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HostKeyPolicy {
+    /// Reject the host.
+    Strict,
+    /// Accept the host and add its key to `~/.ssh/known_hosts`,
+    /// so that it has to match the next time.
+    #[default]
+    AcceptNew,
+}
+
+/// A command that is running on the server.
+///
+/// Reading from it reads the stdout of the command, writing to it
+/// writes to the stdin of the command.
+pub struct RemoteProcess {
+    channel: Channel,
+    waiter: Waiter,
+}
+
+impl RemoteProcess {
+    /// Close the stdin of the command.
+    pub fn close_stdin(&mut self) -> Result<(), Error> {
+        self.waiter.retry(|| self.channel.send_eof()).map_err(Error::SSHExecute)
+    }
+
+    /// Wait for the command to exit and return its exit status and stderr.
+    pub fn finish(mut self) -> Result<(ExitStatus, Vec<u8>), Error> {
         let mut stderr = vec![];
-        channel.stderr().read_to_end(&mut stderr).map_err(Error::SSHReadStdout)?;
+        Retrying(self.channel.stderr(), &self.waiter).read_to_end(&mut stderr).map_err(Error::SSHReadStderr)?;
+
+        self.waiter.retry(|| self.channel.wait_close()).map_err(Error::SSHExecute)?;
+        if self.channel.exit_signal().map_err(Error::SSHExecute)?.exit_signal.is_some() {
+            return Err(Error::SSHCommandStoppedBySignal);
+        }
+
+        let raw_exit_status = self.channel.exit_status().map_err(Error::SSHExecute)?;
+        #[cfg(unix)]
+        let exit_status = {
+            use std::os::unix::process::ExitStatusExt;
+            // `from_raw` expects a wait status, which stores the exit code in the second byte.
+            ExitStatus::from_raw(raw_exit_status << 8)
+        };
+        #[cfg(windows)]
+        let exit_status = {
+            use std::os::windows::process::ExitStatusExt;
+            ExitStatus::from_raw(raw_exit_status as u32)
+        };
+
+        Ok((exit_status, stderr))
+    }
+
+    /// Read the remaining stdout and wait for the command to exit.
+    pub fn wait_with_output(mut self) -> Result<std::process::Output, Error> {
+        let mut stdout = vec![];
+        self.read_to_end(&mut stdout).map_err(Error::SSHReadStdout)?;
+
+        let (status, stderr) = self.finish()?;
 
         Ok(std::process::Output {
-            status: exit_status,
+            status,
             stdout,
             stderr,
         })
     }
 }
+
+impl Read for RemoteProcess {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Retrying(&mut self.channel, &self.waiter).read(buf)
+    }
+}
+
+#[cfg(test)]
+impl SSH {
+    /// A client that is not connected to any server, for tests that never reach the server.
+    pub(crate) fn unconnected(destination: &str) -> Self {
+        let (socket, _) = socket_pair().unwrap();
+        let mut session = Session::new().unwrap();
+        let waiter = Waiter::new(&session, &socket);
+        session.set_tcp_stream(socket);
+
+        Self {
+            session,
+            waiter,
+            destination: destination.to_string(),
+        }
+    }
+}
+
+impl RemoteProcess {
+    /// Get a reader for the stdout of the command that can be moved to another thread,
+    /// so that stdout can be read while stdin is written.
+    pub fn stdout(&self) -> RemoteStdout {
+        RemoteStdout {
+            stream: self.channel.stream(0),
+            waiter: self.waiter.clone(),
+        }
+    }
+}
+
+/// The stdout of a [`RemoteProcess`], see [`RemoteProcess::stdout`].
+pub struct RemoteStdout {
+    stream: ssh2::Stream,
+    waiter: Waiter,
+}
+
+impl Read for RemoteStdout {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Retrying(&mut self.stream, &self.waiter).read(buf)
+    }
+}
+
+impl Write for RemoteProcess {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Retrying(&mut self.channel, &self.waiter).write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Retrying(&mut self.channel, &self.waiter).flush()
+    }
+}
+
+/// `LIBSSH2_ERROR_EAGAIN` from `libssh2.h`, which ssh2 does not export.
+const LIBSSH2_ERROR_EAGAIN: ErrorCode = ErrorCode::Session(-37);
+
+/// `LIBSSH2_ERROR_AUTHENTICATION_FAILED` from `libssh2.h`.
+const LIBSSH2_ERROR_AUTHENTICATION_FAILED: ErrorCode = ErrorCode::Session(-18);
+
+/// How long to wait for the socket at most before a call on the non-blocking session is retried.
+///
+/// Other threads read from the socket too, and may receive the packets this thread waits for
+/// before it wakes up. The call is then retried after this time instead of waiting for more data.
+const WAIT_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Waits until the socket of a non-blocking session is ready in the direction the session is blocked on.
+#[derive(Clone)]
+struct Waiter {
+    session: Session,
+    #[cfg(unix)]
+    socket: std::os::fd::RawFd,
+}
+
+impl Waiter {
+    /// `socket` has to be the stream of `session`, which keeps it open.
+    #[cfg(unix)]
+    fn new(session: &Session, socket: &TcpStream) -> Self {
+        use std::os::fd::AsRawFd;
+
+        Self {
+            session: session.clone(),
+            socket: socket.as_raw_fd(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn new(session: &Session, _socket: &TcpStream) -> Self {
+        Self { session: session.clone() }
+    }
+
+    /// Block until the socket is ready or [`WAIT_TIMEOUT`] has passed.
+    #[cfg(unix)]
+    fn wait(&self) {
+        let events = match self.session.block_directions() {
+            BlockDirections::Outbound => libc::POLLOUT,
+            BlockDirections::Both => libc::POLLIN | libc::POLLOUT,
+            // A channel without data or window space waits for the next packet from the server.
+            BlockDirections::Inbound | BlockDirections::None => libc::POLLIN,
+        };
+        let mut poll_fd = libc::pollfd {
+            fd: self.socket,
+            events,
+            revents: 0,
+        };
+
+        // Errors and interrupts are treated like a timeout, the call is retried either way.
+        // SAFETY: `poll_fd` is a valid `pollfd`, and the session keeps the socket open.
+        unsafe { libc::poll(&mut poll_fd, 1, WAIT_TIMEOUT.as_millis() as libc::c_int) };
+    }
+
+    #[cfg(not(unix))]
+    fn wait(&self) {
+        let _ = self.session.block_directions();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    /// Retry a call on the non-blocking session until it does not need to wait for the socket anymore.
+    fn retry<T>(&self, mut f: impl FnMut() -> Result<T, ssh2::Error>) -> Result<T, ssh2::Error> {
+        loop {
+            match f() {
+                Err(e) if e.code() == LIBSSH2_ERROR_EAGAIN => self.wait(),
+                result => return result,
+            }
+        }
+    }
+
+    /// Retry the I/O of a stream on the non-blocking session until it does not need to wait for the socket anymore.
+    fn retry_io<T>(&self, mut f: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+        loop {
+            match f() {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => self.wait(),
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Makes a stream on the non-blocking session behave like a blocking one.
+struct Retrying<'a, S>(S, &'a Waiter);
+
+impl<S: Read> Read for Retrying<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.1.retry_io(|| self.0.read(buf))
+    }
+}
+
+impl<S: Write> Write for Retrying<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.1.retry_io(|| self.0.write(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.1.retry_io(|| self.0.flush())
+    }
+}
+
+/// Create two connected sockets.
+///
+/// A loopback connection is used instead of a Unix socket pair, which is not available on every platform.
+fn socket_pair() -> std::io::Result<(TcpStream, TcpStream)> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let local = TcpStream::connect(listener.local_addr()?)?;
+    let (remote, _) = listener.accept()?;
+
+    // Another local process could have connected first.
+    if remote.peer_addr()? != local.local_addr()? {
+        return Err(std::io::Error::other("an unexpected process connected to the tunnel"));
+    }
+
+    Ok((local, remote))
+}
+
+/// Start a `ProxyCommand` and return a socket that is connected to its stdin and stdout.
+///
+/// The command runs until the session closes the socket.
+fn proxy(command: &str) -> Result<TcpStream, Error> {
+    // Like `ssh`, the command runs in a shell.
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+
+    let mut child = Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().map_err(Error::SSHTcpConnect)?;
+    let (local, remote) = socket_pair().map_err(Error::SSHTcpConnect)?;
+    let mut remote_reader = remote.try_clone().map_err(Error::SSHTcpConnect)?;
+
+    let (Some(mut stdin), Some(mut stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        unreachable!("stdin and stdout are piped");
+    };
+    // Closing stdin tells the command that the session is done.
+    std::thread::spawn(move || std::io::copy(&mut remote_reader, &mut stdin));
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut stdout, &mut &remote);
+        let _ = remote.shutdown(Shutdown::Both);
+        let _ = child.kill();
+        let _ = child.wait();
+    });
+
+    Ok(local)
+}
+
+/// Copy data between a tunnel channel and the local socket the next session uses.
+///
+/// Each direction is copied by its own thread, which blocks until there is data.
+fn forward(mut channel: Channel, socket: TcpStream, waiter: Waiter) {
+    let Ok(mut socket_reader) = socket.try_clone() else {
+        return;
+    };
+    let upload_waiter = waiter.clone();
+    let mut channel_reader = channel.stream(0);
+
+    let upload = std::thread::spawn(move || {
+        let _ = std::io::copy(&mut socket_reader, &mut Retrying(&mut channel, &upload_waiter));
+        let _ = upload_waiter.retry(|| channel.send_eof());
+        channel
+    });
+
+    let mut socket_writer = &socket;
+    let _ = std::io::copy(&mut Retrying(&mut channel_reader, &waiter), &mut socket_writer);
+    // Stops the upload, if the local side has not closed the socket yet.
+    let _ = socket.shutdown(Shutdown::Both);
+
+    // The jump host is not needed anymore, so a failure to close the channel does not matter.
+    if let Ok(mut channel) = upload.join() {
+        let _ = waiter.retry(|| channel.close());
+    }
+}
+
+/// Split a destination of the form `[user@]host[:port]` into its parts.
+fn parse_destination(destination: &str) -> (Option<&str>, &str, Option<u16>) {
+    let (user, host) = match destination.split_once('@') {
+        Some((user, host)) => (Some(user), host),
+        None => (None, destination),
+    };
+
+    match host.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (user, host, Some(port)),
+            Err(_) => (user, host, None),
+        },
+        _ => (user, host, None),
+    }
+}
+
+/// Reject the server if its host key does not match the one in `~/.ssh/known_hosts`.
+///
+/// Unknown hosts are handled according to `policy`.
+fn check_known_host(session: &Session, hostname: &str, port: u16, policy: HostKeyPolicy) -> Result<(), Error> {
+    let unverified = || Error::SSHHostKeyUnverified { host: hostname.to_string() };
+    let (host_key, _) = session.host_key().ok_or_else(unverified)?;
+    let known_hosts_path = config::ssh_dir().map(|ssh_dir| ssh_dir.join("known_hosts")).ok_or_else(unverified)?;
+    let known_hosts_error = |source| Error::SSHKnownHosts {
+        path: known_hosts_path.display().to_string(),
+        source,
+    };
+
+    let mut known_hosts = session.known_hosts().map_err(Error::SSHConnect)?;
+    if known_hosts_path.exists() {
+        known_hosts
+            .read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+            .map_err(|e| known_hosts_error(e.into()))?;
+    }
+
+    match known_hosts.check_port(hostname, port, host_key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(Error::SSHHostKeyMismatch { host: hostname.to_string() }),
+        CheckResult::NotFound => match policy {
+            HostKeyPolicy::Strict => Err(Error::SSHHostKeyUnknown { host: hostname.to_string() }),
+            HostKeyPolicy::AcceptNew => {
+                record_known_host(&known_hosts_path, hostname, port, host_key).map_err(known_hosts_error)?;
+                crate::logging::warn(hostname, &format!("added the host key to {}", known_hosts_path.display()));
+                Ok(())
+            }
+        },
+        CheckResult::Failure => Err(unverified()),
+    }
+}
+
+/// Append a host key to a known hosts file, creating it if needed.
+fn record_known_host(path: &Path, hostname: &str, port: u16, host_key: &[u8]) -> std::io::Result<()> {
+    let line = known_hosts_line(hostname, port, host_key)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "the host key has no type"))?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+
+    // The entry has to start on its own line.
+    let mut last_byte = [b'\n'];
+    if file.seek(SeekFrom::End(0))? > 0 {
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last_byte)?;
+    }
+    let separator = if last_byte[0] == b'\n' { "" } else { "\n" };
+
+    file.write_all(format!("{}{}", separator, line).as_bytes())
+}
+
+/// Format a host key as a line of a known hosts file, like `ssh` does.
+fn known_hosts_line(hostname: &str, port: u16, host_key: &[u8]) -> Option<String> {
+    // The key starts with its type, prefixed by its length as four big-endian bytes.
+    let type_len = u32::from_be_bytes(host_key.get(..4)?.try_into().ok()?) as usize;
+    let key_type = std::str::from_utf8(host_key.get(4..4 + type_len)?).ok()?;
+
+    let host = match port {
+        22 => hostname.to_string(),
+        _ => format!("[{}]:{}", hostname, port),
+    };
+    Some(format!("{} {} {}\n", host, key_type, BASE64_STANDARD.encode(host_key)))
+}
+
+/// Authenticate using the SSH agent, the given identity files or the default keys.
+fn authenticate(session: &Session, user: &str, identity_files: &[PathBuf], agent: bool) -> Result<(), Error> {
+    // Reported if there is nothing to try.
+    let mut last_error = ssh2::Error::new(LIBSSH2_ERROR_AUTHENTICATION_FAILED, "no authentication method was available");
+    if agent {
+        match session.userauth_agent(user) {
+            Ok(()) => return Ok(()),
+            Err(e) => last_error = e,
+        }
+    }
+
+    let default_identity_files = config::ssh_dir()
+        .into_iter()
+        .flat_map(|ssh_dir| ["id_ed25519", "id_ecdsa", "id_rsa"].map(|name| ssh_dir.join(name)));
+
+    for identity_file in identity_files.iter().cloned().chain(default_identity_files) {
+        if !identity_file.exists() {
+            continue;
+        }
+
+        match session.userauth_pubkey_file(user, None, &identity_file, None) {
+            Ok(()) => return Ok(()),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(Error::SSHAuth(last_error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_destination_test() {
+        assert_eq!(parse_destination("host"), (None, "host", None));
+        assert_eq!(parse_destination("bob@host"), (Some("bob"), "host", None));
+        assert_eq!(parse_destination("bob@host:2222"), (Some("bob"), "host", Some(2222)));
+        assert_eq!(parse_destination("::1"), (None, "::1", None));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn record_known_host_test() {
+        let mut host_key = vec![0, 0, 0, 11];
+        host_key.extend_from_slice(b"ssh-ed25519");
+        host_key.extend_from_slice(&[0, 0, 0, 32]);
+        host_key.extend_from_slice(&[7; 32]);
+
+        let path = std::env::temp_dir().join(crate::transfer::temporary_file_name(Path::new("known_hosts")));
+        // An existing entry without a trailing newline.
+        std::fs::write(&path, known_hosts_line("other", 22, &host_key).unwrap().trim_end()).unwrap();
+
+        record_known_host(&path, "web", 22, &host_key).unwrap();
+        record_known_host(&path, "web", 2222, &host_key).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let mut known_hosts = Session::new().unwrap().known_hosts().unwrap();
+        known_hosts.read_file(&path, KnownHostFileKind::OpenSSH).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(contents.lines().count(), 3);
+        assert!(contents.lines().nth(2).unwrap().starts_with("[web]:2222 ssh-ed25519 AAAAC3NzaC1lZDI1NTE5"));
+        assert!(matches!(known_hosts.check_port("web", 22, &host_key), CheckResult::Match));
+        assert!(matches!(known_hosts.check_port("web", 2222, &host_key), CheckResult::Match));
+        assert!(matches!(known_hosts.check_port("api", 22, &host_key), CheckResult::NotFound));
+        assert!(matches!(known_hosts.check_port("other", 22, &[7; 8]), CheckResult::Mismatch));
+        assert_eq!(known_hosts_line("web", 22, &[0, 0, 0, 11]), None);
+    }
+
+    #[test]
+    fn proxy_test() {
+        let mut socket = proxy("cat").unwrap();
+        socket.write_all(b"hello").unwrap();
+        socket.shutdown(Shutdown::Write).unwrap();
+
+        let mut echoed = String::new();
+        socket.read_to_string(&mut echoed).unwrap();
+        assert_eq!(echoed, "hello");
+    }
+
+    #[test]
+    fn shared_ssh_test() {
+        fn shared<T: Clone + Send + Sync>() {}
+        shared::<SSH>();
+    }
+
+    #[test]
+    fn concurrent_retry_test() {
+        use std::sync::{
+            Arc,
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+        };
+
+        let (socket, mut server) = socket_pair().unwrap();
+        let waiter = Waiter::new(&Session::new().unwrap(), &socket);
+        let ready = Arc::new(AtomicBool::new(false));
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let (waiter, ready, attempts) = (waiter.clone(), ready.clone(), attempts.clone());
+                std::thread::spawn(move || {
+                    waiter.retry(|| {
+                        attempts.fetch_add(1, Ordering::SeqCst);
+                        match ready.load(Ordering::SeqCst) {
+                            true => Ok(()),
+                            false => Err(ssh2::Error::new(LIBSSH2_ERROR_EAGAIN, "would block")),
+                        }
+                    })
+                })
+            })
+            .collect();
+
+        // The threads wait for the socket instead of retrying all the time.
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(attempts.load(Ordering::SeqCst) < 4 * 30);
+
+        // Data on the socket wakes up every thread.
+        ready.store(true, Ordering::SeqCst);
+        server.write_all(&[0]).unwrap();
+        for thread in threads {
+            thread.join().unwrap().unwrap();
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::Error;
+
+/// The settings for a single host from the local SSH config.
+///
+/// They are resolved by `ssh -G`, so the config is read exactly like `ssh`
+/// reads it, including `Include`, `Match` blocks and tokens like `%h`.
+/// Only the options that are needed to connect are kept.
+#[derive(Debug, Default, PartialEq)]
+pub struct HostConfig {
+    pub hostname: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_files: Vec<PathBuf>,
+    /// The jump hosts from `ProxyJump`. `ProxyJump none` results in an empty list.
+    pub proxy_jump: Option<Vec<String>>,
+    /// The command from `ProxyCommand`, whose stdin and stdout are used instead of a connection.
+    /// Its tokens like `%h` are not replaced yet.
+    pub proxy_command: Option<String>,
+}
+
+impl HostConfig {
+    /// Resolve the settings for `host` with `ssh -G`.
+    ///
+    /// If `ssh` is not installed, no settings apply.
+    pub fn load(host: &str) -> Result<Self, Error> {
+        let output = Command::new("ssh")
+            .arg("-G")
+            .arg("--")
+            .arg(host)
+            .stdin(Stdio::null())
+            .stderr(Stdio::piped())
+            .output();
+
+        match output {
+            Ok(output) if output.status.success() => Ok(Self::parse(&String::from_utf8_lossy(&output.stdout))),
+            Ok(output) => Err(Error::SSHConfigParse(std::io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error::SSHConfigParse(e)),
+        }
+    }
+
+    /// Extract the settings from the output of `ssh -G`, which prints one lowercase option and its value per line.
+    pub fn parse(output: &str) -> Self {
+        let mut host_config = Self::default();
+
+        for line in output.lines() {
+            let Some((key, value)) = line.trim().split_once(' ') else {
+                continue;
+            };
+
+            match key {
+                "hostname" => host_config.hostname = Some(value.to_string()),
+                "user" => host_config.user = Some(value.to_string()),
+                "port" => host_config.port = value.parse().ok(),
+                "identityfile" => host_config.identity_files.push(expand_tilde(value)),
+                "proxyjump" => {
+                    host_config.proxy_jump = Some(match value {
+                        "none" => vec![],
+                        _ => value.split(',').map(|jump_host| jump_host.trim().to_string()).collect(),
+                    });
+                }
+                "proxycommand" if value != "none" => host_config.proxy_command = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        host_config
+    }
+}
+
+/// The directory containing the user's SSH configuration.
+pub(crate) fn ssh_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(".ssh"))
+}
+
+fn expand_tilde(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Replace the tokens `%h`, `%p`, `%r`, `%n` and `%%` in a `ProxyCommand`.
+pub(crate) fn expand_tokens(command: &str, alias: &str, hostname: &str, port: u16, user: &str) -> String {
+    let mut expanded = String::with_capacity(command.len());
+
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('h') => expanded.push_str(hostname),
+            Some('p') => expanded.push_str(&port.to_string()),
+            Some('r') => expanded.push_str(user),
+            Some('n') => expanded.push_str(alias),
+            Some('%') => expanded.push('%'),
+            // Unknown tokens are passed on unchanged.
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let output = "\
+user deploy
+hostname build.example.com
+port 2222
+identityfile /keys/build
+identityfile /keys/other
+proxyjump bastion,admin@gateway:2222
+forwardagent no
+";
+
+        assert_eq!(
+            HostConfig::parse(output),
+            HostConfig {
+                hostname: Some("build.example.com".to_string()),
+                user: Some("deploy".to_string()),
+                port: Some(2222),
+                identity_files: vec![PathBuf::from("/keys/build"), PathBuf::from("/keys/other")],
+                proxy_jump: Some(vec!["bastion".to_string(), "admin@gateway:2222".to_string()]),
+                proxy_command: None,
+            }
+        );
+
+        let config = HostConfig::parse("hostname web\nproxycommand nc %h %p\n");
+        assert_eq!(config.proxy_command.as_deref(), Some("nc %h %p"));
+        assert_eq!(HostConfig::parse("proxycommand none\n").proxy_command, None);
+    }
+
+    #[test]
+    fn expand_tokens_test() {
+        assert_eq!(
+            expand_tokens("ssh -W %h:%p -l %r gateway # %n 100%% %x", "web", "web.example.com", 2222, "bob"),
+            "ssh -W web.example.com:2222 -l bob gateway # web 100% %x"
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use std::{
+    cell::RefCell,
+    io::{BufRead, BufReader, Read},
+    marker::PhantomData,
+    rc::Rc,
+    sync::mpsc::Receiver,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    Bytes, Error,
+    client::Uploads,
+    frame::{FrameKind, Message},
+    logging::LogRecord,
+    progress::Progress,
+    serde::Headers,
+    ssh::{RemoteProcess, SSH},
+};
+
+/// The responses of a streaming route.
+///
+/// Each response is decoded as soon as the server sends it. If the server
+/// process fails, the last item will be the error.
+pub struct ResponseStream<R> {
+    ssh: SSH,
+    route: &'static str,
+    // The user the server process runs as through sudo, if any.
+    run_as: Option<&'static str>,
+    output: Option<Output>,
+    // A message that was received while the requests were written, but is not a log record or progress update.
+    pending: Option<std::io::Result<Message>>,
+    headers: Option<Headers>,
+    // Kept until the stream is dropped, so that the uploaded files
+    // are available for as long as the server process runs.
+    uploads: Uploads,
+    _response: PhantomData<fn() -> R>,
+}
+
+/// Where the messages on the stdout of the server process are read from.
+enum Output {
+    Direct(BufReader<RemoteProcess>),
+    /// stdout is read by a thread, so that the server never blocks on a full stdout while the requests are written.
+    Background { process: RemoteProcess, messages: Receiver<std::io::Result<Message>> },
+    /// The output of a process that never ran.
+    #[cfg(test)]
+    Fake {
+        stdout: BufReader<std::io::Cursor<Vec<u8>>>,
+        status: std::process::ExitStatus,
+        stderr: Vec<u8>,
+    },
+}
+
+impl Output {
+    fn next_message(&mut self) -> std::io::Result<Option<Message>> {
+        match self {
+            Output::Direct(reader) => crate::frame::read_message(reader),
+            // The thread stops at the end of stdout.
+            Output::Background { messages, .. } => messages.recv().map_or(Ok(None), |message| message.map(Some)),
+            #[cfg(test)]
+            Output::Fake { stdout, .. } => crate::frame::read_message(stdout),
+        }
+    }
+
+    /// Wait for the process to exit and return its exit status and stderr.
+    fn finish(self) -> Result<(std::process::ExitStatus, Vec<u8>), Error> {
+        match self {
+            Output::Direct(reader) => reader.into_inner().finish(),
+            Output::Background { process, .. } => process.finish(),
+            #[cfg(test)]
+            Output::Fake { status, stderr, .. } => Ok((status, stderr)),
+        }
+    }
+}
+
+impl<R> ResponseStream<R> {
+    #[doc(hidden)]
+    pub fn new(ssh: SSH, route: &'static str, run_as: Option<&'static str>, process: RemoteProcess, uploads: Uploads) -> Self {
+        Self::with_output(ssh, route, run_as, Output::Direct(BufReader::new(process)), uploads)
+    }
+
+    /// Like [`ResponseStream::new`], but stdout is read by a thread while the requests are written with [`ResponseStream::input`].
+    pub(crate) fn new_in_background(ssh: SSH, route: &'static str, run_as: Option<&'static str>, process: RemoteProcess, uploads: Uploads) -> Self {
+        let (sender, messages) = std::sync::mpsc::channel();
+        let mut stdout = BufReader::new(process.stdout());
+        std::thread::spawn(move || {
+            loop {
+                let message = match crate::frame::read_message(&mut stdout) {
+                    Ok(Some(message)) => Ok(message),
+                    Ok(None) => break,
+                    Err(e) => Err(e),
+                };
+                let is_error = message.is_err();
+                if sender.send(message).is_err() || is_error {
+                    break;
+                }
+            }
+        });
+
+        Self::with_output(ssh, route, run_as, Output::Background { process, messages }, uploads)
+    }
+
+    fn with_output(ssh: SSH, route: &'static str, run_as: Option<&'static str>, output: Output, uploads: Uploads) -> Self {
+        Self {
+            ssh,
+            route,
+            run_as,
+            output: Some(output),
+            pending: None,
+            headers: None,
+            uploads,
+            _response: PhantomData,
+        }
+    }
+
+    /// The headers of the last response, or `None` if the server is too old to send them.
+    pub fn headers(&self) -> Option<&Headers> {
+        self.headers.as_ref()
+    }
+
+    /// The server process and the uploads of the call, to write more requests to its stdin.
+    pub(crate) fn input(&mut self) -> Option<(&mut RemoteProcess, &mut Uploads)> {
+        match self.output.as_mut()? {
+            Output::Direct(reader) => Some((reader.get_mut(), &mut self.uploads)),
+            Output::Background { process, .. } => Some((process, &mut self.uploads)),
+            #[cfg(test)]
+            Output::Fake { .. } => None,
+        }
+    }
+
+    /// Handle the log records and progress updates that arrived in the background so far, without waiting for more.
+    pub(crate) fn handle_received(&mut self, on_progress: &mut dyn FnMut(Progress)) {
+        while self.pending.is_none() {
+            let Some(Output::Background { messages, .. }) = &self.output else {
+                return;
+            };
+            let Ok(message) = messages.try_recv() else {
+                return;
+            };
+
+            self.pending = match message {
+                Ok(message) => self.handle_control(message, on_progress).map(Ok),
+                Err(e) => Some(Err(e)),
+            };
+        }
+    }
+
+    /// Emit a log record or pass a progress update to `on_progress`, or return the message if it is neither.
+    fn handle_control(&self, message: Message, on_progress: &mut dyn FnMut(Progress)) -> Option<Message> {
+        match message {
+            // Log records and progress updates are sent between the responses.
+            Message::Line(line) => {
+                if let Some(encoded_record) = line.trim().strip_prefix(crate::logging::LOG_PREFIX) {
+                    if let Ok(record) = crate::serde::decode_response::<LogRecord>(encoded_record) {
+                        crate::logging::emit(record, self.ssh.destination(), self.route);
+                    }
+                    return None;
+                }
+
+                if let Some(encoded_progress) = line.trim().strip_prefix(crate::progress::PROGRESS_PREFIX) {
+                    if let Ok(progress) = crate::serde::decode_response::<Progress>(encoded_progress) {
+                        on_progress(progress);
+                    }
+                    return None;
+                }
+
+                Some(Message::Line(line))
+            }
+            Message::Frame(frame) => match frame.kind {
+                FrameKind::Log => {
+                    if let Ok(record) = crate::serde::decode_response_bytes::<LogRecord>(&frame.marker, frame.body) {
+                        crate::logging::emit(record, self.ssh.destination(), self.route);
+                    }
+                    None
+                }
+                FrameKind::Progress => {
+                    if let Ok(progress) = crate::serde::decode_response_bytes::<Progress>(&frame.marker, frame.body) {
+                        on_progress(progress);
+                    }
+                    None
+                }
+                FrameKind::Payload | FrameKind::Spill => Some(Message::Frame(frame)),
+            },
+        }
+    }
+
+    fn next_message(&mut self) -> std::io::Result<Option<Message>> {
+        if let Some(message) = self.pending.take() {
+            return message.map(Some);
+        }
+        match self.output.as_mut() {
+            Some(output) => output.next_message(),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<R: for<'de> Deserialize<'de>> ResponseStream<R> {
+    /// Get the next response and pass the progress updates that
+    /// arrive before it to `on_progress`.
+    pub(crate) fn next_with_progress(&mut self, on_progress: &mut dyn FnMut(Progress)) -> Option<Result<R, Error>> {
+        self.output.as_ref()?;
+
+        loop {
+            let message = match self.next_message() {
+                Ok(None) => break,
+                Ok(Some(message)) => message,
+                Err(e) => {
+                    self.output = None;
+                    return Some(Err(Error::SSHReadStdout(e)));
+                }
+            };
+
+            let response = match self.handle_control(message, on_progress) {
+                None => continue,
+                Some(Message::Line(line)) => crate::client::decode_response(&self.ssh, self.run_as, line.trim()),
+                Some(Message::Frame(frame)) => crate::client::decode_response_frame(&self.ssh, self.run_as, frame),
+            };
+            return Some(Self::take_headers(&mut self.headers, response));
+        }
+
+        // The server closed stdout, so the process is done.
+        match self.output.take()?.finish() {
+            Ok((status, _)) if status.success() => None,
+            Ok((_, stderr)) => Some(Err(crate::client::process_error(&stderr, self.run_as))),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl<R> ResponseStream<R> {
+    /// Keep the headers of a decoded response for [`ResponseStream::headers`].
+    fn take_headers(last_headers: &mut Option<Headers>, response: Result<(R, Option<Headers>), Error>) -> Result<R, Error> {
+        let (response, headers) = response?;
+        *last_headers = headers;
+        Ok(response)
+    }
+}
+
+impl<R: for<'de> Deserialize<'de>> Iterator for ResponseStream<R> {
+    type Item = Result<R, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_progress(&mut |_| {})
+    }
+}
+
+/// The requests of a route that streams its requests.
+///
+/// Each request is decoded as soon as the client sends it. If a request
+/// cannot be read or decoded, the stream ends early and the error is
+/// reported to the client instead of the response.
+pub struct RequestStream<R> {
+    reader: Box<dyn BufRead>,
+    error: Rc<RefCell<Option<Error>>>,
+    _request: PhantomData<fn() -> R>,
+}
+
+impl<R> RequestStream<R> {
+    #[doc(hidden)]
+    pub fn new(reader: impl BufRead + 'static) -> Self {
+        Self {
+            reader: Box::new(reader),
+            error: Rc::default(),
+            _request: PhantomData,
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn error_handle(&self) -> RequestStreamError {
+        RequestStreamError(self.error.clone())
+    }
+}
+
+impl<R: for<'de> Deserialize<'de>> Iterator for RequestStream<R> {
+    type Item = R;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.borrow().is_some() {
+            return None;
+        }
+
+        let result = match crate::frame::read_message(&mut self.reader) {
+            Ok(None) => return None,
+            Ok(Some(message)) => crate::serde::decode_request_message(message),
+            Err(e) => Err(Error::ReadRequest(e)),
+        };
+
+        match result {
+            Ok(request) => Some(request),
+            Err(e) => {
+                *self.error.borrow_mut() = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// Gives access to the error that ended a [`RequestStream`] after
+/// the stream was moved into the user logic.
+#[doc(hidden)]
+pub struct RequestStreamError(Rc<RefCell<Option<Error>>>);
+
+impl RequestStreamError {
+    pub fn take(&self) -> Option<Error> {
+        self.0.borrow_mut().take()
+    }
+}
+
+/// The requests for a route that streams its requests, read from a [`Read`] in chunks.
+///
+/// Routes that stream their requests take an iterator, so a reader can be passed to a route
+/// whose request type is [`Bytes`] with this, e.g. to stream a file to the server. If reading
+/// fails, the requests end early, and the error can be taken from [`ReadChunks::error_handle`].
+pub struct ReadChunks<T> {
+    reader: T,
+    chunk_size: usize,
+    error: Rc<RefCell<Option<std::io::Error>>>,
+}
+
+impl<T: Read> ReadChunks<T> {
+    /// Read chunks of at most 64 KiB from `reader`.
+    pub fn new(reader: T) -> Self {
+        Self {
+            reader,
+            chunk_size: 64 * 1024,
+            error: Rc::default(),
+        }
+    }
+
+    /// Read chunks of at most `chunk_size` bytes instead.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Gives access to the error that ended the chunks after they were passed to the route.
+    pub fn error_handle(&self) -> ReadChunksError {
+        ReadChunksError(self.error.clone())
+    }
+}
+
+impl<T: Read> Iterator for ReadChunks<T> {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Bytes> {
+        if self.error.borrow().is_some() {
+            return None;
+        }
+
+        let mut chunk = vec![];
+        match (&mut self.reader).take(self.chunk_size as u64).read_to_end(&mut chunk) {
+            Ok(0) => None,
+            Ok(_) => Some(chunk.into()),
+            Err(e) => {
+                *self.error.borrow_mut() = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// The error that ended a [`ReadChunks`], if there was one.
+pub struct ReadChunksError(Rc<RefCell<Option<std::io::Error>>>);
+
+impl ReadChunksError {
+    pub fn take(&self) -> Option<std::io::Error> {
+        self.0.borrow_mut().take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn response_stream_test() {
+        use std::os::unix::process::ExitStatusExt;
+
+        // Two responses with a log record and a progress update in between,
+        // from a server process that fails afterwards.
+        let progress = Progress {
+            percentage: Some(50.0),
+            message: "halfway".to_string(),
+        };
+        let record = LogRecord {
+            level: crate::LogLevel::Info,
+            target: "beyond_example".to_string(),
+            message: "counting".to_string(),
+        };
+        let stdout = format!(
+            "{}\n{}{}\n{}{}\n{}\n",
+            crate::serde::encode_response(1u32).unwrap(),
+            crate::logging::LOG_PREFIX,
+            crate::serde::encode_response_with(crate::Encoding::Json, crate::Compression::None, record).unwrap(),
+            crate::progress::PROGRESS_PREFIX,
+            crate::serde::encode_response_with(crate::Encoding::Json, crate::Compression::None, progress.clone()).unwrap(),
+            crate::serde::encode_response(2u32).unwrap(),
+        );
+
+        let ssh = SSH::unconnected("test");
+        let output = Output::Fake {
+            stdout: BufReader::new(std::io::Cursor::new(stdout.into_bytes())),
+            status: std::process::ExitStatus::from_raw(1 << 8),
+            stderr: b"something went wrong\n".to_vec(),
+        };
+        let mut responses = ResponseStream::<u32>::with_output(ssh.clone(), "count", None, output, Uploads::new(&ssh));
+
+        let mut updates = vec![];
+        assert_eq!(responses.next_with_progress(&mut |progress| updates.push(progress)).unwrap().unwrap(), 1);
+        assert_eq!(responses.next_with_progress(&mut |progress| updates.push(progress)).unwrap().unwrap(), 2);
+        assert_eq!(updates, vec![progress]);
+
+        // The exit status is checked once stdout is closed.
+        assert!(matches!(
+            responses.next(),
+            Some(Err(Error::SSHProcessExecute { stderr })) if stderr == "something went wrong"
+        ));
+        assert!(responses.next().is_none());
+    }
+
+    #[test]
+    fn request_stream_test() {
+        let encoded_requests = format!(
+            "{}\n{}\n",
+            crate::serde::encode_request(1).unwrap(),
+            crate::serde::encode_request(2).unwrap(),
+        );
+
+        let requests = RequestStream::<u32>::new(std::io::Cursor::new(encoded_requests));
+        let error = requests.error_handle();
+
+        assert_eq!(requests.collect::<Vec<_>>(), vec![1, 2]);
+        assert!(error.take().is_none());
+    }
+
+    #[test]
+    fn request_stream_error_test() {
+        let encoded_requests = format!("{}\nnot base64\n{}\n", crate::serde::encode_request(1).unwrap(), crate::serde::encode_request(2).unwrap());
+
+        let requests = RequestStream::<u32>::new(std::io::Cursor::new(encoded_requests));
+        let error = requests.error_handle();
+
+        assert_eq!(requests.collect::<Vec<_>>(), vec![1]);
+        assert!(matches!(error.take(), Some(Error::Base64DecodeRequest(_))));
+    }
+
+    #[test]
+    fn read_chunks_test() {
+        let chunks = ReadChunks::new(&b"hello world"[..]).chunk_size(4);
+        let error = chunks.error_handle();
+
+        let chunks: Vec<Vec<u8>> = chunks.map(Bytes::into_vec).collect();
+        assert_eq!(chunks, vec![b"hell".to_vec(), b"o wo".to_vec(), b"rld".to_vec()]);
+        assert!(error.take().is_none());
+
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("disk on fire"))
+            }
+        }
+
+        let chunks = ReadChunks::new(Failing);
+        let error = chunks.error_handle();
+        assert_eq!(chunks.count(), 0);
+        assert_eq!(error.take().unwrap().to_string(), "disk on fire");
+    }
+}
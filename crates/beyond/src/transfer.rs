@@ -0,0 +1,307 @@
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Error, exec::shell_quote, ssh::SSH};
+
+/// A file that is transferred over SFTP instead of being embedded in
+/// a request or response.
+///
+/// When a request containing a `RemoteFile` is sent, the generated client
+/// uploads the local file at [`RemoteFile::path`] to a new file in the temporary
+/// directory of the server, which only the SSH user can read, and the user logic
+/// receives a `RemoteFile` pointing to the uploaded file. The uploaded file is
+/// deleted once the call finished.
+///
+/// When a response contains a `RemoteFile`, the file on the server is
+/// downloaded to a new temporary file on the client, and the client receives
+/// a `RemoteFile` pointing to the downloaded file. The downloaded file is deleted
+/// once the `RemoteFile` and all its clones are dropped, unless [`RemoteFile::keep`]
+/// is called. The file on the server is not deleted.
+#[derive(Clone, Debug)]
+pub struct RemoteFile {
+    path: PathBuf,
+    // Set if this is a file that was downloaded to a temporary path.
+    downloaded: Option<Arc<DownloadedFile>>,
+}
+
+impl RemoteFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            downloaded: None,
+        }
+    }
+
+    /// The path of the file on the machine this value is used on.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Keep a downloaded file after this value and its clones are dropped, and return its path.
+    pub fn keep(self) -> PathBuf {
+        if let Some(downloaded) = &self.downloaded {
+            downloaded.keep.store(true, Ordering::Relaxed);
+        }
+        self.path
+    }
+}
+
+impl PartialEq for RemoteFile {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Eq for RemoteFile {}
+
+/// Deletes a downloaded file once the last [`RemoteFile`] pointing to it is dropped.
+#[derive(Debug)]
+struct DownloadedFile {
+    path: PathBuf,
+    keep: AtomicBool,
+}
+
+impl Drop for DownloadedFile {
+    fn drop(&mut self) {
+        if !self.keep.load(Ordering::Relaxed) {
+            // The file may not have been downloaded because the call failed.
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl Serialize for RemoteFile {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // While the client encodes a request, the file is sent to a temporary path
+        // on the server, and that path is what the server receives.
+        let path = with_transfers(|transfers| {
+            let remote = transfers.temp_dir.join(temporary_file_name(&self.path));
+            transfers.transfers.push(Transfer {
+                local: self.path.clone(),
+                remote: remote.clone(),
+            });
+            remote
+        })
+        .unwrap_or_else(|| self.path.clone());
+
+        path.to_string_lossy().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RemoteFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let path = PathBuf::from(String::deserialize(deserializer)?);
+
+        // While the client decodes a response, the file is fetched to a temporary
+        // path on the client, and that path is what the client receives.
+        let downloaded = with_transfers(|transfers| {
+            let local = transfers.temp_dir.join(temporary_file_name(&path));
+            transfers.transfers.push(Transfer {
+                local: local.clone(),
+                remote: path.clone(),
+            });
+            local
+        });
+
+        Ok(match downloaded {
+            Some(local) => Self {
+                path: local.clone(),
+                downloaded: Some(Arc::new(DownloadedFile {
+                    path: local,
+                    keep: AtomicBool::new(false),
+                })),
+            },
+            None => Self::new(path),
+        })
+    }
+}
+
+/// A file that needs to be copied between the client and the server.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Transfer {
+    pub(crate) local: PathBuf,
+    pub(crate) remote: PathBuf,
+}
+
+/// The transfers that are collected while a payload is encoded or decoded.
+struct Transfers {
+    // The temporary directory on the other machine, where the files are copied to.
+    temp_dir: PathBuf,
+    transfers: Vec<Transfer>,
+}
+
+thread_local! {
+    static TRANSFERS: RefCell<Option<Transfers>> = const { RefCell::new(None) };
+}
+
+/// Run `f` and collect the transfers of all [`RemoteFile`]s that are
+/// serialized or deserialized while it runs.
+///
+/// The files are copied to new files in `temp_dir`, which is on the server for
+/// requests and on the client for responses.
+pub(crate) fn collect_transfers<T>(temp_dir: &Path, f: impl FnOnce() -> T) -> (T, Vec<Transfer>) {
+    let transfers = Transfers {
+        temp_dir: temp_dir.to_path_buf(),
+        transfers: vec![],
+    };
+    let previous = TRANSFERS.with(|current| current.borrow_mut().replace(transfers));
+    let value = f();
+    let transfers = TRANSFERS.with(|current| std::mem::replace(&mut *current.borrow_mut(), previous));
+
+    (value, transfers.map(|transfers| transfers.transfers).unwrap_or_default())
+}
+
+fn with_transfers<T>(f: impl FnOnce(&mut Transfers) -> T) -> Option<T> {
+    TRANSFERS.with(|transfers| transfers.borrow_mut().as_mut().map(f))
+}
+
+/// Copy a local file to a new file on the server for a [`RemoteFile`] in a request.
+///
+/// A route that runs as another user through sudo cannot read the private files of the
+/// SSH user, so the file is written as that user through sudo instead of over SFTP.
+pub(crate) fn upload(ssh: &SSH, local: &Path, remote: &Path, run_as: Option<&str>) -> Result<(), Error> {
+    let Some(user) = run_as else {
+        return ssh.upload_new(local, remote);
+    };
+
+    let mut local_file = std::fs::File::open(local).map_err(Error::SSHFileTransfer)?;
+    // `set -C` refuses to overwrite an existing file, like the upload over SFTP.
+    let script = "umask 077 && set -C && cat > \"$1\"";
+    let mut process = ssh.spawn(&format!("sudo -n -u {} -- sh -c {} sh {}", shell_quote(user), shell_quote(script), shell_quote(&remote.to_string_lossy())))?;
+    std::io::copy(&mut local_file, &mut process).map_err(Error::SSHFileTransfer)?;
+    process.close_stdin()?;
+
+    check_sudo(process.finish()?, user)
+}
+
+/// Copy the file of a [`RemoteFile`] in a response from the server to a new local file.
+///
+/// The files of a route that runs as another user through sudo are read as that user
+/// through sudo, since the SSH user may not be able to read them over SFTP.
+pub(crate) fn download(ssh: &SSH, remote: &Path, local: &Path, run_as: Option<&str>) -> Result<(), Error> {
+    let Some(user) = run_as else {
+        return ssh.download_new(remote, local);
+    };
+
+    let mut local_file = create_private_file(local).map_err(Error::SSHFileTransfer)?;
+    let mut process = ssh.execute_streaming(&format!("sudo -n -u {} -- cat -- {}", shell_quote(user), shell_quote(&remote.to_string_lossy())))?;
+    std::io::copy(&mut process, &mut local_file).map_err(Error::SSHFileTransfer)?;
+
+    check_sudo(process.finish()?, user)
+}
+
+/// Delete an uploaded file from the server, as the user it was uploaded as.
+pub(crate) fn remove(ssh: &SSH, remote: &Path, run_as: Option<&str>) -> Result<(), Error> {
+    let Some(user) = run_as else {
+        return ssh.remove(remote);
+    };
+
+    let output = ssh.execute(&format!("sudo -n -u {} -- rm -f -- {}", shell_quote(user), shell_quote(&remote.to_string_lossy())))?;
+    check_sudo((output.status, output.stderr), user)
+}
+
+fn check_sudo((status, stderr): (std::process::ExitStatus, Vec<u8>), user: &str) -> Result<(), Error> {
+    match status.success() {
+        true => Ok(()),
+        false => Err(crate::client::process_error(&stderr, Some(user))),
+    }
+}
+
+/// Create a new file that only the current user can read.
+///
+/// Fails if the file already exists, so that a temporary file cannot be prepared by someone else.
+pub(crate) fn create_private_file(path: &Path) -> std::io::Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)
+}
+
+/// Generate a unique file name that keeps the name of the original file
+/// to make it recognizable.
+pub(crate) fn temporary_file_name(original: &Path) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos())
+        .unwrap_or_default();
+    let file_name = original.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+
+    format!(
+        "beyond-{}-{}-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        nanos,
+        file_name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+    struct Request {
+        input: RemoteFile,
+    }
+
+    #[test]
+    fn remote_file_upload_test() {
+        let request = Request {
+            input: RemoteFile::new("data/input.csv"),
+        };
+
+        let (encoded_request, transfers) = collect_transfers(Path::new("/var/tmp"), || crate::serde::encode_request(&request).unwrap());
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].local, PathBuf::from("data/input.csv"));
+        assert!(transfers[0].remote.starts_with("/var/tmp"));
+        assert!(transfers[0].remote.to_string_lossy().ends_with("-input.csv"));
+
+        // The server sees the path the file was uploaded to.
+        let decoded_request: Request = crate::serde::decode_request(&encoded_request).unwrap();
+        assert_eq!(decoded_request.input.path(), transfers[0].remote);
+    }
+
+    #[test]
+    fn remote_file_download_test() {
+        let encoded_response = crate::serde::encode_response(RemoteFile::new("/srv/output.txt")).unwrap();
+
+        let decode = || crate::serde::decode_response::<RemoteFile>(&encoded_response).unwrap();
+        let (response, transfers) = collect_transfers(&std::env::temp_dir(), decode);
+        assert_eq!(
+            transfers,
+            vec![Transfer {
+                local: response.path().to_path_buf(),
+                remote: PathBuf::from("/srv/output.txt"),
+            }]
+        );
+
+        // The downloaded file is deleted with the last clone.
+        std::fs::write(response.path(), "output").unwrap();
+        let path = response.path().to_path_buf();
+        let clone = response.clone();
+        drop(response);
+        assert!(path.exists());
+        drop(clone);
+        assert!(!path.exists());
+
+        // Unless it is kept.
+        let (response, _) = collect_transfers(&std::env::temp_dir(), decode);
+        std::fs::write(response.path(), "output").unwrap();
+        let path = response.clone().keep();
+        drop(response);
+        assert!(path.exists());
+        std::fs::remove_file(path).unwrap();
+    }
+}
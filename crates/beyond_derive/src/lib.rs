@@ -19,15 +19,19 @@ fn beyond_derive_impl(input: proc_macro::TokenStream) -> syn::Result<proc_macro:
     let mut output = proc_macro2::TokenStream::new();
 
     // This will contain wrappers around the server-side functions.
-    // They will return `Result<String, String>`'s, which is required
-    // because they cannot use the un-encoded structs, as this would
-    // clash with the type system.
+    // They write the encoded responses to stdout themselves, which is
+    // required because they cannot return the un-encoded structs, as this
+    // would clash with the type system.
     let mut serverside_wrappers = proc_macro2::TokenStream::new();
 
     // This will contain match arms that call the respective server-side wrappers
     // depending on the route that the user chose.
     let mut serverside_routing = proc_macro2::TokenStream::new();
 
+    // This will contain the descriptions of all routes for `Server::ROUTES`,
+    // including the fingerprints the client compares with the server's in the handshake.
+    let mut route_infos = proc_macro2::TokenStream::new();
+
     // Loop over each attribute of the annotated item.
     for attribute in input.attrs {
         // Extract the identifier of the attribute.
@@ -64,6 +68,8 @@ fn beyond_derive_impl(input: proc_macro::TokenStream) -> syn::Result<proc_macro:
                 // for the route.
                 let serverside_routing_tokens = route.to_serverside_routing_tokens();
                 serverside_routing.extend(serverside_routing_tokens);
+
+                route_infos.extend(route.to_route_info_tokens());
             }
             _ => continue, // Ignore all other attributes.
         }
@@ -71,9 +77,17 @@ fn beyond_derive_impl(input: proc_macro::TokenStream) -> syn::Result<proc_macro:
 
     // Add the core logic to the final code.
     output.extend(quote::quote! {
+        #[derive(Clone)]
         pub struct Client {
             ssh: ::beyond::ssh::SSH,
             server_binary: String,
+            log_level: ::core::option::Option<::beyond::LogLevel>,
+            exec_options: ::beyond::ExecOptions,
+            wire: ::beyond::client::Wire,
+            headers: ::beyond::Headers,
+            deploy_options: ::beyond::DeployOptions,
+            // Shared between the copies of a client, so that the handshake happens only once.
+            handshake: ::std::sync::Arc<::std::sync::OnceLock<::beyond::handshake::Handshake>>,
         }
 
         impl Client {
@@ -81,45 +95,250 @@ fn beyond_derive_impl(input: proc_macro::TokenStream) -> syn::Result<proc_macro:
                 Ok(Self {
                     ssh: ::beyond::ssh::SSH::new(destination)?,
                     server_binary,
+                    log_level: ::core::option::Option::None,
+                    exec_options: ::beyond::ExecOptions::new(),
+                    wire: ::core::default::Default::default(),
+                    headers: ::beyond::Headers::new(),
+                    deploy_options: ::beyond::DeployOptions::new(),
+                    handshake: ::core::default::Default::default(),
                 })
             }
 
-            pub fn check_server(&mut self) -> ::core::result::Result<(), ::beyond::Error> {
-                let output = self.ssh.execute(&format!("which {}", self.server_binary))?;
+            /// Create a client that uses an existing session, e.g. one
+            /// that was connected through jump hosts with [`::beyond::ssh::SSH::connect`].
+            pub fn with_ssh(ssh: ::beyond::ssh::SSH, server_binary: String) -> Self {
+                Self {
+                    ssh,
+                    server_binary,
+                    log_level: ::core::option::Option::None,
+                    exec_options: ::beyond::ExecOptions::new(),
+                    wire: ::core::default::Default::default(),
+                    headers: ::beyond::Headers::new(),
+                    deploy_options: ::beyond::DeployOptions::new(),
+                    handshake: ::core::default::Default::default(),
+                }
+            }
+
+            /// Forward log records of at least `level` from the server.
+            ///
+            /// They are re-emitted locally with `tracing` or `log`, depending on
+            /// which feature of `beyond` is enabled, tagged with the host and route.
+            pub fn forward_logs(&mut self, level: ::core::option::Option<::beyond::LogLevel>) {
+                self.log_level = level;
+            }
+
+            /// Set the options, like the environment and working directory, that
+            /// the server process is started with for every call.
+            pub fn set_exec_options(&mut self, exec_options: ::beyond::ExecOptions) {
+                self.exec_options = exec_options;
+            }
+
+            /// Get a copy of this client whose calls use `exec_options` on top of
+            /// the options of this client, e.g. for a single call.
+            ///
+            /// The copy shares the SSH session with this client.
+            pub fn with_exec_options(&self, exec_options: &::beyond::ExecOptions) -> Self {
+                let mut client = self.clone();
+                client.exec_options = self.exec_options.merge(exec_options);
+                client
+            }
+
+            /// Set the codec that requests and responses are encoded with.
+            ///
+            /// If the server binary does not support the codec, calls use JSON instead and a warning is logged.
+            /// A warning is also logged if the codec is not self-describing but routes are `evolving`.
+            pub fn set_encoding(&mut self, encoding: ::beyond::Encoding) {
+                ::beyond::handshake::check_encoding(&self.ssh, encoding, &#server_ident::route_fingerprints());
+                self.wire.encoding = encoding;
+            }
+
+            /// Set how large requests and responses are compressed.
+            ///
+            /// If the server binary does not support the compression, calls are not compressed and a warning is logged.
+            pub fn set_compression(&mut self, compression: ::beyond::Compression) {
+                self.wire.compression = compression;
+            }
+
+            /// Set how requests and responses are sent between the client and the server.
+            ///
+            /// Binary framing skips base64 and checks every message with a checksum. If the server
+            /// binary does not support it, calls use text framing instead and a warning is logged.
+            pub fn set_framing(&mut self, framing: ::beyond::Framing) {
+                self.wire.framing = framing;
+            }
+
+            /// Set the headers, like a trace context or custom headers, that are sent with every call.
+            ///
+            /// A request ID and the client version are added to every call unless they are set here.
+            pub fn set_headers(&mut self, headers: ::beyond::Headers) {
+                self.headers = headers;
+            }
+
+            /// Get a copy of this client whose calls send `headers` on top of
+            /// the headers of this client, e.g. for a single call.
+            pub fn with_headers(&self, headers: &::beyond::Headers) -> Self {
+                let mut client = self.clone();
+                client.headers = self.headers.merge(headers);
+                client
+            }
+
+            // Build the command that invokes the server binary with the given arguments, as `run_as` if given.
+            // The options of beyond itself are passed to the server process as environment variables.
+            fn server_command(&self, arguments: &str, progress: bool, run_as: ::core::option::Option<&str>, wire: ::beyond::client::Wire) -> ::core::result::Result<String, ::beyond::Error> {
+                let mut exec_options = self.exec_options.clone();
+                if let ::core::option::Option::Some(level) = self.log_level {
+                    exec_options = exec_options.env(::beyond::logging::LOG_LEVEL_ENV, level.to_string());
+                }
+                if progress {
+                    exec_options = exec_options.env(::beyond::progress::PROGRESS_ENV, "1");
+                }
+                if wire.encoding != ::beyond::Encoding::Json {
+                    exec_options = exec_options.env(::beyond::codec::CODEC_ENV, wire.encoding.name());
+                }
+                if wire.compression != ::beyond::Compression::None {
+                    exec_options = exec_options.env(::beyond::compression::COMPRESSION_ENV, wire.compression.name());
+                }
+                if wire.framing != ::beyond::Framing::Text {
+                    exec_options = exec_options.env(::beyond::frame::FRAMING_ENV, wire.framing.name());
+                }
+                exec_options.command_as(&format!("{} beyond-server-process {}", self.server_binary, arguments), run_as)
+            }
+
+            /// Exchange versions with the server binary and check that the client can talk to it.
+            ///
+            /// This happens automatically before the first call. A server with a different
+            /// protocol version is refused. A different app version and routes whose types
+            /// differ are logged as a warning, and calls to those routes fail.
+            pub fn handshake(&self) -> ::core::result::Result<::beyond::ServerHello, ::beyond::Error> {
+                ::core::result::Result::Ok(self.cached_handshake()?.server_hello.clone())
+            }
+
+            /// The routes that the server binary does not have, or has with different
+            /// request or response types than the client.
+            pub fn incompatible_routes(&self) -> ::core::result::Result<::std::vec::Vec<String>, ::beyond::Error> {
+                ::core::result::Result::Ok(self.cached_handshake()?.incompatible_routes.clone())
+            }
+
+            fn cached_handshake(&self) -> ::core::result::Result<&::beyond::handshake::Handshake, ::beyond::Error> {
+                if let ::core::option::Option::Some(handshake) = self.handshake.get() {
+                    return ::core::result::Result::Ok(handshake);
+                }
+
+                // The handshake uses the default wire options, since the client does not know yet which ones the server supports.
+                let command = self.server_command(::beyond::handshake::HELLO, false, ::core::option::Option::None, ::core::default::Default::default())?;
+                let handshake = ::beyond::handshake::handshake(&self.ssh, &command, env!("CARGO_PKG_VERSION"), &#server_ident::route_fingerprints())?;
+                ::core::result::Result::Ok(self.handshake.get_or_init(|| handshake))
+            }
+
+            /// Ask the server binary which routes it supports.
+            pub fn list_routes(&self) -> ::core::result::Result<::std::vec::Vec<::beyond::RouteDescription>, ::beyond::Error> {
+                self.handshake()?;
+
+                let process = self.ssh.execute_streaming(&self.server_command(::beyond::routes::ROUTES, false, ::core::option::Option::None, ::core::default::Default::default())?)?;
+                ::core::result::Result::Ok(::beyond::client::receive_response(self.ssh.clone(), ::beyond::routes::ROUTES, ::core::option::Option::None, process, ::beyond::client::Uploads::new(&self.ssh), &mut |_| {})?.0)
+            }
+
+            // The wire options of this client, limited to the ones the server binary supports.
+            fn negotiated_wire(&self) -> ::core::result::Result<::beyond::client::Wire, ::beyond::Error> {
+                ::core::result::Result::Ok(::beyond::handshake::negotiate_wire(&self.ssh, self.wire, &self.cached_handshake()?.server_hello))
+            }
+
+            // Make sure the server binary has the route with the same types before calling it.
+            fn check_route(&self, route: &str) -> ::core::result::Result<(), ::beyond::Error> {
+                ::beyond::handshake::check_route(self.cached_handshake()?, route)
+            }
+
+            /// Upload a local file to the server over SFTP.
+            pub fn upload(&self, local: &::std::path::Path, remote: &::std::path::Path) -> ::core::result::Result<(), ::beyond::Error> {
+                self.ssh.upload(local, remote)
+            }
+
+            /// Download a file from the server over SFTP.
+            pub fn download(&self, remote: &::std::path::Path, local: &::std::path::Path) -> ::core::result::Result<(), ::beyond::Error> {
+                self.ssh.download(remote, local)
+            }
+
+            /// Set how [`Client::deploy`] installs the server binary, and whether
+            /// [`Client::check_server`] deploys it automatically.
+            pub fn set_deploy_options(&mut self, deploy_options: ::beyond::DeployOptions) {
+                self.deploy_options = deploy_options;
+            }
+
+            /// Upload the server binary over SFTP, make it executable and verify its hash.
+            pub fn deploy(&self) -> ::core::result::Result<(), ::beyond::Error> {
+                ::beyond::deploy::deploy(&self.ssh, &self.deploy_options, &self.server_binary)
+            }
 
-                if output.status.success() {
-                    ::core::result::Result::Ok(())
-                } else {
-                    ::core::result::Result::Err(::beyond::Error::ServerComponentNotInstalled)
+            /// Check that the server binary is installed, runs and is a `beyond` server,
+            /// and get information about it and the machine it runs on.
+            ///
+            /// With auto-deploy enabled, it is deployed first if it is missing or outdated.
+            pub fn check_server(&self) -> ::core::result::Result<::beyond::ServerInfo, ::beyond::Error> {
+                if self.deploy_options.is_auto_deploy() {
+                    ::beyond::deploy::ensure_deployed(&self.ssh, &self.deploy_options, &self.server_binary)?;
                 }
+
+                ::beyond::info::check_server(&self.ssh, &self.server_command(::beyond::info::INFO, false, ::core::option::Option::None, ::core::default::Default::default())?)
             }
         }
 
+        // The client is shared between threads to run calls concurrently,
+        // so it has to stay `Send` and `Sync`.
+        const _: () = {
+            fn assert_send_sync<T: ::core::marker::Send + ::core::marker::Sync>() {}
+            let _ = assert_send_sync::<Client>;
+        };
+
         impl #server_ident {
             // Insert the server-side wrappers around the user logic here.
             #serverside_wrappers
 
+            /// The routes of this server, in the order they are declared.
+            pub const ROUTES: &'static [::beyond::RouteInfo] = &[#route_infos];
+
+            /// Describe every route with the JSON Schemas of its request and response.
+            ///
+            /// Types only have a schema if they implement `schemars::JsonSchema` and
+            /// the `json-schema` feature of `beyond` is enabled.
+            pub fn json_schema() -> ::beyond::schema::Value {
+                ::beyond::schema::routes_schema(Self::ROUTES)
+            }
+
+            #[doc(hidden)]
+            pub fn route_fingerprints() -> ::std::vec::Vec<::beyond::handshake::RouteFingerprint> {
+                Self::ROUTES.iter().map(::beyond::RouteInfo::fingerprint).collect()
+            }
+
             pub fn run(server: #server_ident) -> ::core::option::Option<::std::process::ExitCode> {
                 // Check if the should actually run.
                 if ::std::env::args().nth(1).unwrap_or_default() != "beyond-server-process" {
                     return ::core::option::Option::None;
                 }
 
+                // Forward log records to the client if it asked for them.
+                ::beyond::logging::install();
+
                 // Get the route and it's request to use.
                 let route_name = ::std::env::args().nth(2).unwrap_or_default();
                 let encoded_request = ::std::env::args().nth(3).unwrap_or_default();
 
-                // Call the function associated with the route.
-                let encoded_response_result = match route_name.as_str() {
+                // Call the function associated with the route, which writes
+                // the encoded response(s) to stdout. Stdout is not locked for the
+                // whole call, so that log records can be written in between.
+                let mut output = ::std::io::stdout();
+                let result = match route_name.as_str() {
+                    // The handshake is built into every server binary.
+                    name if name == ::beyond::handshake::HELLO => ::beyond::handshake::write_hello(&mut output, env!("CARGO_PKG_VERSION"), Self::route_fingerprints()),
+                    name if name == ::beyond::routes::ROUTES => ::beyond::routes::write_routes(&mut output, Self::ROUTES),
+                    name if name == ::beyond::info::INFO => ::beyond::info::write_info(&mut output, env!("CARGO_PKG_VERSION"), Self::ROUTES.len()),
                     #serverside_routing
                     _ => ::core::result::Result::Err(::beyond::Error::InvalidRoute { route_name }),
                 };
 
-                // Check if the function succeeded and print the result
-                // to stdout or stderr accordingly.
-                match encoded_response_result {
-                    Ok(encoded_response) => {
-                        println!("{}", encoded_response);
+                // Check if the function succeeded and print the error
+                // to stderr if it did not.
+                match result {
+                    Ok(()) => {
                         return Some(::std::process::ExitCode::SUCCESS);
                     }
                     Err(e) => {
@@ -11,6 +11,14 @@ pub struct Route {
     request: Ident,
     /// The type of the response.
     response: Ident,
+    /// Whether the route returns a stream of responses instead of a single one.
+    stream_response: bool,
+    /// Whether the route takes a stream of requests instead of a single one.
+    stream_request: bool,
+    /// The user to run the server process as through sudo.
+    run_as: Option<String>,
+    /// Whether the types follow the rules of `beyond::evolution`, so that they may differ between the client and the server.
+    evolving: bool,
 }
 
 impl Route {
@@ -20,29 +28,122 @@ impl Route {
         let request = &self.request;
         let response = &self.response;
 
-        quote! {
-            pub fn #name(&mut self, request: #request) -> ::core::result::Result<#response, ::beyond::Error> {
-                // Prepare the request to be used as a command-line argument.
-                let encoded_request = ::beyond::serde::encode_request(request)?;
+        // Only routes with `run_as` are elevated, all others run as the SSH user.
+        let run_as = match &self.run_as {
+            Some(user) => quote! { ::core::option::Option::Some(#user) },
+            None => quote! { ::core::option::Option::None },
+        };
+
+        if self.stream_response {
+            return quote! {
+                pub fn #name(&self, request: #request) -> ::core::result::Result<::beyond::ResponseStream<#response>, ::beyond::Error> {
+                    self.check_route(stringify!(#name))?;
 
-                let output = self.ssh.execute(&format!("{} beyond-server-process {} {}", self.server_binary, stringify!(#name), encoded_request))?;
+                    // Upload the files the request contains and start the server process with the request,
+                    // without waiting for it, so that the responses can be decoded while they arrive.
+                    let wire = self.negotiated_wire()?;
+                    let headers = ::beyond::client::call_headers(&self.headers, env!("CARGO_PKG_VERSION"));
+                    let mut uploads = ::beyond::client::Uploads::new(&self.ssh).with_temp_dir(self.cached_handshake()?.server_hello.temp_dir.as_deref()).with_run_as(#run_as);
+                    let process = ::beyond::client::start_call(&self.ssh, &self.server_command(stringify!(#name), false, #run_as, wire)?, wire, &headers, request, &mut uploads)?;
 
-                // Check if the execution succeeded and handle the failure case.
-                if !output.status.success() {
-                    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-                    todo!()
-                    // return ::core::result::Result::Err(::beyond::Error::SSHProcessExecute { stderr });
+                    Ok(::beyond::ResponseStream::new(self.ssh.clone(), stringify!(#name), #run_as, process, uploads))
                 }
+            };
+        }
+
+        let with_progress = quote::format_ident!("{}_with_progress", name);
+        let with_response_headers = quote::format_ident!("{}_with_response_headers", name);
+        let call = quote::format_ident!("{}_call", name);
+
+        // Routes with a single response can report progress before the response arrives,
+        // so a second method that takes a progress callback is generated for them.
+        let (request_parameter, call_body) = if self.stream_request {
+            let request_parameter = quote! { requests: impl ::core::iter::IntoIterator<Item = #request> };
+            let call_body = quote! {
+                // The requests are sent over stdin instead of as a command-line argument. They share the headers of the call.
+                let wire = self.negotiated_wire()?;
+                let process = self.ssh.spawn(&self.server_command(stringify!(#name), on_progress.is_some(), #run_as, wire)?)?;
+                let headers = ::beyond::client::call_headers(&self.headers, env!("CARGO_PKG_VERSION"));
+                let uploads = ::beyond::client::Uploads::new(&self.ssh).with_temp_dir(self.cached_handshake()?.server_hello.temp_dir.as_deref()).with_run_as(#run_as);
+
+                ::beyond::client::send_requests(self.ssh.clone(), stringify!(#name), #run_as, process, wire, &headers, requests, uploads, on_progress.unwrap_or(&mut |_| {}))
+            };
+            (request_parameter, call_body)
+        } else {
+            let request_parameter = quote! { request: #request };
+            let call_body = quote! {
+                // Upload the files the request contains and start the server process with the request.
+                let wire = self.negotiated_wire()?;
+                let headers = ::beyond::client::call_headers(&self.headers, env!("CARGO_PKG_VERSION"));
+                let mut uploads = ::beyond::client::Uploads::new(&self.ssh).with_temp_dir(self.cached_handshake()?.server_hello.temp_dir.as_deref()).with_run_as(#run_as);
+                let process = ::beyond::client::start_call(&self.ssh, &self.server_command(stringify!(#name), on_progress.is_some(), #run_as, wire)?, wire, &headers, request, &mut uploads)?;
+
+                // Decode the response, download the files it contains and check if the execution succeeded.
+                ::beyond::client::receive_response(self.ssh.clone(), stringify!(#name), #run_as, process, uploads, on_progress.unwrap_or(&mut |_| {}))
+            };
+            (request_parameter, call_body)
+        };
+        let request_argument = if self.stream_request { quote! { requests } } else { quote! { request } };
 
-                // Extract the response and decode it.
-                let encoded_response = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                let response = ::beyond::serde::decode_response(&encoded_response)?;
+        quote! {
+            pub fn #name(&self, #request_parameter) -> ::core::result::Result<#response, ::beyond::Error> {
+                ::core::result::Result::Ok(self.#call(#request_argument, ::core::option::Option::None)?.0)
+            }
+
+            /// Like the method without the `_with_progress` suffix, but `on_progress` is
+            /// called for every progress update the server reports.
+            pub fn #with_progress(&self, #request_parameter, mut on_progress: impl ::core::ops::FnMut(::beyond::Progress)) -> ::core::result::Result<#response, ::beyond::Error> {
+                ::core::result::Result::Ok(self.#call(#request_argument, ::core::option::Option::Some(&mut on_progress))?.0)
+            }
 
-                Ok(response)
+            /// Like the method without the `_with_response_headers` suffix, but the headers of the
+            /// response are returned as well. They are `None` if the server is too old to send them.
+            pub fn #with_response_headers(&self, #request_parameter) -> ::core::result::Result<(#response, ::core::option::Option<::beyond::Headers>), ::beyond::Error> {
+                self.#call(#request_argument, ::core::option::Option::None)
+            }
+
+            #[doc(hidden)]
+            fn #call(&self, #request_parameter, on_progress: ::core::option::Option<&mut dyn ::core::ops::FnMut(::beyond::Progress)>) -> ::core::result::Result<(#response, ::core::option::Option<::beyond::Headers>), ::beyond::Error> {
+                self.check_route(stringify!(#name))?;
+                #call_body
             }
         }
     }
 
+    // Generate the description of the route for `Server::ROUTES`, as an element of a slice.
+    pub fn to_route_info_tokens(&self) -> TokenStream {
+        let name = &self.name;
+        let request = &self.request;
+        let response = &self.response;
+        let stream_request = self.stream_request;
+        let stream_response = self.stream_response;
+        let evolving = self.evolving;
+
+        quote! {
+            ::beyond::RouteInfo {
+                name: stringify!(#name),
+                request: stringify!(#request),
+                response: stringify!(#response),
+                stream_request: #stream_request,
+                stream_response: #stream_response,
+                evolving: #evolving,
+                request_fingerprint: ::beyond::fingerprint::fingerprint::<#request>,
+                response_fingerprint: ::beyond::fingerprint::fingerprint::<#response>,
+                request_schema: || {
+                    // Resolves to `WithSchema` if the type has a schema, see `::beyond::schema::Probe`.
+                    #[allow(unused_imports)]
+                    use ::beyond::schema::{WithSchema as _, WithoutSchema as _};
+                    (&::beyond::schema::Probe::<#request>::new()).json_schema()
+                },
+                response_schema: || {
+                    #[allow(unused_imports)]
+                    use ::beyond::schema::{WithSchema as _, WithoutSchema as _};
+                    (&::beyond::schema::Probe::<#response>::new()).json_schema()
+                },
+            },
+        }
+    }
+
     // Generate the server-side wrapper function around the user logic.
     pub fn to_serverside_wrapper_tokens(&self, server_ident: &Ident) -> TokenStream {
         let name = &self.name;
@@ -51,13 +152,51 @@ impl Route {
 
         let ident = quote::format_ident!("{}_wrapper", name);
 
+        // Streaming requests are read from stdin while the user logic consumes them.
+        let decode = if self.stream_request {
+            quote! {
+                // There is no request on the command line for streaming requests.
+                drop(encoded_request);
+                let request = ::beyond::RequestStream::<#request>::new(::std::io::stdin().lock());
+                let request_error = request.error_handle();
+            }
+        } else {
+            quote! {
+                let request: #request = ::beyond::serde::read_request(&encoded_request)?;
+            }
+        };
+
+        // Streaming routes write every response as soon as the user logic yields it.
+        let respond = if self.stream_response {
+            quote! {
+                for response in server.#name(request) {
+                    let response: #response = response;
+                    ::beyond::serde::write_response(output, response)?;
+                }
+            }
+        } else if self.stream_request {
+            quote! {
+                let response: #response = server.#name(request);
+                // A request that could not be decoded takes precedence over the response,
+                // since the user logic did not see all requests.
+                if let ::core::option::Option::Some(e) = request_error.take() {
+                    return ::core::result::Result::Err(e);
+                }
+                ::beyond::serde::write_response(output, response)?;
+            }
+        } else {
+            quote! {
+                let response: #response = server.#name(request);
+                ::beyond::serde::write_response(output, response)?;
+            }
+        };
+
         quote! {
             #[doc(hidden)]
-            fn #ident(server: #server_ident, encoded_request: String) -> ::core::result::Result<String, ::beyond::Error> {
-                let request: #request = ::beyond::serde::decode_request(&encoded_request)?;
-                let response: #response = server.#name(request);
-                let encoded_response = ::beyond::serde::encode_response(response)?;
-                ::core::result::Result::Ok(encoded_response)
+            fn #ident(server: #server_ident, encoded_request: String, output: &mut impl ::std::io::Write) -> ::core::result::Result<(), ::beyond::Error> {
+                #decode
+                #respond
+                ::core::result::Result::Ok(())
             }
         }
     }
@@ -69,7 +208,7 @@ impl Route {
         let ident = quote::format_ident!("{}_wrapper", name);
 
         quote! {
-            stringify!(#name) => Self::#ident(server, encoded_request),
+            stringify!(#name) => Self::#ident(server, encoded_request, &mut output),
         }
     }
 }
@@ -80,10 +219,81 @@ impl syn::parse::Parse for Route {
         let request = input.parse()?;
         let response = input.parse()?;
 
+        let mut stream_response = false;
+        let mut stream_request = false;
+        let mut run_as = None;
+        let mut evolving = false;
+
+        // Options are given as a comma-separated list after the types,
+        // e.g. `#[beyond_route(logs LogsRequest LogLine, stream_response, run_as = "root")]`.
+        while !input.is_empty() {
+            input.parse::<syn::Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+
+            let option: Ident = input.parse()?;
+            match option.to_string().as_str() {
+                "stream_response" => stream_response = true,
+                "stream_request" => stream_request = true,
+                "evolving" => evolving = true,
+                "run_as" => {
+                    input.parse::<syn::Token![=]>()?;
+                    run_as = Some(input.parse::<syn::LitStr>()?.value());
+                }
+                _ => return Err(syn::Error::new(option.span(), format!("unknown route option '{}'", option))),
+            }
+        }
+
+        // The caller only gets the responses once all requests are sent,
+        // so streaming in both directions could block forever.
+        if stream_request && stream_response {
+            return Err(input.error("a route cannot use both `stream_request` and `stream_response`"));
+        }
+
         Ok(Self {
             name,
             request,
             response,
+            stream_response,
+            stream_request,
+            run_as,
+            evolving,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_run_as_test() {
+        let route: Route = syn::parse_str(r#"logs LogsRequest LogLine, stream_response, run_as = "root""#).unwrap();
+        assert_eq!(route.name, "logs");
+        assert!(route.stream_response);
+        assert_eq!(route.run_as.as_deref(), Some("root"));
+
+        let route: Route = syn::parse_str("hello HelloRequest HelloResponse").unwrap();
+        assert_eq!(route.run_as, None);
+
+        // The user has to be a string literal.
+        assert!(syn::parse_str::<Route>("hello HelloRequest HelloResponse, run_as = root").is_err());
+        assert!(syn::parse_str::<Route>("hello HelloRequest HelloResponse, run_as").is_err());
+    }
+
+    #[test]
+    fn parse_evolving_test() {
+        let route: Route = syn::parse_str("hello HelloRequest HelloResponse, evolving").unwrap();
+        assert!(route.evolving);
+        assert!(!route.stream_request && !route.stream_response);
+
+        let route: Route = syn::parse_str("sum SumRequest SumResponse, stream_request, evolving,").unwrap();
+        assert!(route.evolving && route.stream_request);
+
+        let route: Route = syn::parse_str("hello HelloRequest HelloResponse").unwrap();
+        assert!(!route.evolving);
+
+        assert!(syn::parse_str::<Route>("hello HelloRequest HelloResponse, evolve").is_err());
+    }
+}
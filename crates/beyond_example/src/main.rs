@@ -4,8 +4,10 @@ use beyond::Error;
 use serde::{Deserialize, Serialize};
 
 // The structure of the data that will be sent to the server
-// to process.
+// to process. With the `json-schema` feature, the types also derive
+// `schemars::JsonSchema`, so that their schemas can be exported.
 #[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 pub struct HelloRequest {
     pub name: String,
 }
@@ -13,10 +15,39 @@ pub struct HelloRequest {
 // The structure of the data that will be sent back from
 // the server.
 #[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 pub struct HelloResponse {
     pub message: String,
 }
 
+// The request for the `countdown` route, which streams
+// one response per number instead of a single response.
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct CountdownRequest {
+    pub from: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct CountdownTick {
+    pub remaining: u32,
+}
+
+// The request for the `sum` route. The client sends any number
+// of these, and the server receives them one by one.
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct SumItem {
+    pub value: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct SumResponse {
+    pub sum: u64,
+}
+
 // The generated code should always be kept in a seperate module
 // to prevent name collisions.
 mod beyond_impl {
@@ -25,6 +56,8 @@ mod beyond_impl {
     // The `Server` struct will contain the server-side implementations of all routes.
     #[derive(beyond::Beyond)]
     #[beyond_route(hello HelloRequest HelloResponse)] // Because proc macros cannot access impl blocks, each route needs to be specified here.
+    #[beyond_route(countdown CountdownRequest CountdownTick, stream_response)] // Routes marked with `stream_response` return multiple responses.
+    #[beyond_route(sum SumItem SumResponse, stream_request)] // Routes marked with `stream_request` take multiple requests.
     pub struct Server;
 
     impl Server {
@@ -44,13 +77,45 @@ mod beyond_impl {
             .trim()
             .to_string();
 
+            // The headers the client sent along with the request are available while handling it.
+            let headers = beyond::headers();
+            let greeting = headers.get("greeting").unwrap_or("Hello");
+            beyond::set_response_header("hostname", &hostname);
+
             HelloResponse {
                 message: format!(
-                    "Hello, {}! This message was generated on '{}'.",
-                    request.name, hostname
+                    "{}, {}! This message was generated on '{}'.",
+                    greeting, request.name, hostname
                 ),
             }
         }
+
+        // The implementation of the `countdown` route. Because it is marked with
+        // `stream_response`, it returns an iterator of responses. Each response
+        // is sent to the client as soon as the iterator yields it.
+        pub fn countdown(&self, request: CountdownRequest) -> impl Iterator<Item = CountdownTick> {
+            (0..=request.from).rev().map(|remaining| {
+                std::thread::sleep(std::time::Duration::from_millis(500));
+                CountdownTick { remaining }
+            })
+        }
+
+        // The implementation of the `sum` route. Because it is marked with
+        // `stream_request`, it receives an iterator of requests, which are
+        // decoded as they arrive from the client.
+        // It also reports its progress, which the client can display while
+        // waiting for the response.
+        pub fn sum(&self, items: beyond::RequestStream<SumItem>) -> SumResponse {
+            let mut sum = 0;
+            for (index, item) in items.enumerate() {
+                sum += item.value;
+                if (index + 1) % 25 == 0 {
+                    beyond::report_progress(None, format!("added {} numbers", index + 1));
+                }
+            }
+
+            SumResponse { sum }
+        }
     }
 }
 
@@ -79,6 +144,13 @@ fn run() -> Result<ExitCode, Error> {
     // If the program reaches this point, the server did not get executed.
     // This means the client should run.
 
+    // The JSON Schemas of the requests and responses of all routes can be
+    // printed for other tooling. They are also part of `Client::list_routes`.
+    if std::env::args().nth(1).as_deref() == Some("--json-schema") {
+        println!("{:#}", beyond_impl::Server::json_schema());
+        return Ok(ExitCode::SUCCESS);
+    }
+
     // Parse the CLI arguments for the client.
     let name = match std::env::args().nth(2) {
         Some(name) => name,
@@ -94,16 +166,61 @@ fn run() -> Result<ExitCode, Error> {
     // Create an instance of the client that was fully generated by `beyond`.
     // It takes the SSH host to connect to and the binary to run on the server
     // as arguments.
-    let mut client = beyond_impl::Client::new(&destination, "beyond_example".to_string())?;
+    let client = beyond_impl::Client::new(&destination, "beyond_example".to_string())?;
 
     // Check if the server is correctly set up.
-    client.check_server()?;
+    let info = client.check_server()?;
+    println!("connected to '{}' ({}) with {} routes", info.hostname, info.arch, info.route_count);
+
+    // The routes the server binary supports can be listed, just like the
+    // ones of the local binary in `Server::ROUTES`.
+    for route in client.list_routes()? {
+        println!("route '{}' takes {} and returns {}", route.name, route.request, route.response);
+    }
 
     // Execute one of the functions that was defined on the server.
     // It has the exact same signature, but executes it on the server
     // by invoking the server binary over SSH.
-    let response = client.hello(HelloRequest { name })?;
+    let response = client.hello(HelloRequest { name: name.clone() })?;
+    println!("{}", response.message);
+
+    // Headers are sent along with the request, e.g. to pass a trace context
+    // or custom values. `with_headers` returns a copy of the client for this call.
+    let headers = beyond::Headers::new().header("greeting", "Hi");
+    let response = client.with_headers(&headers).hello(HelloRequest { name: name.clone() })?;
     println!("{}", response.message);
 
+    // The `_with_response_headers` variant also returns the headers the server answered with.
+    let (_, response_headers) = client.hello_with_response_headers(HelloRequest { name })?;
+    if let Some(hostname) = response_headers.as_ref().and_then(|headers| headers.get("hostname")) {
+        println!("Answered by '{}'.", hostname);
+    }
+
+    // Streaming routes return an iterator over the responses, which
+    // yields each response as soon as it arrives from the server.
+    for tick in client.countdown(CountdownRequest { from: 3 })? {
+        println!("{}...", tick?.remaining);
+    }
+
+    // Routes with streaming requests take an iterator, which is sent to
+    // the server while it is consumed.
+    // Every route with a single response also has a `_with_progress` method,
+    // which calls the given closure for each progress update from the server.
+    let response = client.sum_with_progress((1..=100).map(|value| SumItem { value }), |progress| {
+        println!("progress: {}", progress.message);
+    })?;
+    println!("The sum of 1 to 100 is {}.", response.sum);
+
+    // The client can be shared between threads. Every call runs on its own
+    // channel of the same SSH session, so these calls run at the same time.
+    let client = &client;
+    std::thread::scope(|scope| -> Result<(), Error> {
+        let handles = ["Alice", "Bob"].map(|name| scope.spawn(move || client.hello(HelloRequest { name: name.to_string() })));
+        for handle in handles {
+            println!("{}", handle.join().expect("the thread should not panic")?.message);
+        }
+        Ok(())
+    })?;
+
     Ok(ExitCode::SUCCESS)
 }